@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX functions_tenant_idx;
+ALTER TABLE functions DROP COLUMN tenant;
+DROP TABLE api_keys;
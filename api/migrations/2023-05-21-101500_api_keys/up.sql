@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+  id SERIAL PRIMARY KEY,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  tenant VARCHAR NOT NULL,
+  key_hash VARCHAR NOT NULL UNIQUE,
+  scopes TEXT[] NOT NULL DEFAULT '{}',
+  revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+ALTER TABLE functions ADD COLUMN tenant VARCHAR;
+CREATE INDEX functions_tenant_idx ON functions (tenant);
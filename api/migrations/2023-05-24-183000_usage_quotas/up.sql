@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE usage_quotas (
+  tenant VARCHAR NOT NULL,
+  -- Days since UNIX epoch (UTC)
+  day INTEGER NOT NULL,
+  invocations INTEGER NOT NULL DEFAULT 0,
+  deploys INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (tenant, day)
+);
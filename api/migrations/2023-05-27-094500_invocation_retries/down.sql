@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE invoke_requests DROP COLUMN attempts;
+ALTER TABLE functions DROP COLUMN idempotent;
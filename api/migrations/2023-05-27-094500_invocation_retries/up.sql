@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE functions ADD COLUMN idempotent BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE invoke_requests ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE functions DROP CONSTRAINT functions_quorum_check;
+ALTER TABLE functions DROP COLUMN quorum;
+ALTER TABLE functions DROP COLUMN replicas;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE functions ADD COLUMN replicas INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE functions ADD COLUMN quorum INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE functions ADD CONSTRAINT functions_quorum_check CHECK (quorum BETWEEN 1 AND replicas);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE functions DROP COLUMN min_tier;
+DROP TABLE worker_reputations;
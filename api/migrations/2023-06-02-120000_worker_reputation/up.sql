@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE worker_reputations (
+  identity VARCHAR PRIMARY KEY,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  tier VARCHAR NOT NULL,
+  successes BIGINT NOT NULL DEFAULT 0,
+  timeouts BIGINT NOT NULL DEFAULT 0,
+  malformed BIGINT NOT NULL DEFAULT 0,
+  disagreements BIGINT NOT NULL DEFAULT 0,
+  -- Moving average of reply latency
+  latency_ms DOUBLE PRECISION NOT NULL DEFAULT 0
+);
+
+ALTER TABLE functions ADD COLUMN min_tier VARCHAR NOT NULL DEFAULT 'anonymous';
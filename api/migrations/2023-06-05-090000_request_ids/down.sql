@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE invoke_requests DROP COLUMN request_id;
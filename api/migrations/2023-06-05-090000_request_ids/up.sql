@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE invoke_requests ADD COLUMN request_id VARCHAR;
+CREATE INDEX invoke_requests_request_id ON invoke_requests (request_id);
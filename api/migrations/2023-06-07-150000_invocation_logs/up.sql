@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE invocation_logs (
+  id SERIAL PRIMARY KEY,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- Id of the HTTP request, i.e. invoke_requests.request_id
+  request_id VARCHAR NOT NULL,
+  -- Id of the request sent to the worker that logged
+  dispatch_id VARCHAR NOT NULL,
+  level VARCHAR NOT NULL,
+  message TEXT NOT NULL
+);
+
+CREATE INDEX invocation_logs_request_id ON invocation_logs (request_id);
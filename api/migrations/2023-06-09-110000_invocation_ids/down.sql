@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE invocation_logs DROP COLUMN invocation_id;
+ALTER TABLE invoke_requests DROP COLUMN invocation_id;
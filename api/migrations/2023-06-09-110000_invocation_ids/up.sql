@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Callers pick request ids freely, so logs are tied to the invocation
+-- instead, by an id unique to it that its dispatch ids derive from
+ALTER TABLE invoke_requests ADD COLUMN invocation_id VARCHAR;
+CREATE UNIQUE INDEX invoke_requests_invocation_id ON invoke_requests (invocation_id);
+
+ALTER TABLE invocation_logs ADD COLUMN invocation_id VARCHAR;
+CREATE INDEX invocation_logs_invocation_id ON invocation_logs (invocation_id);
@@ -0,0 +1,111 @@
+use super::error::Error as DBError;
+use crate::db::{schema::api_keys, DBPoolConnection};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{str::FromStr, time::SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Deploy,
+    Invoke,
+    /// Connect as a semi-trusted worker.
+    Worker,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deploy => "deploy",
+            Self::Invoke => "invoke",
+            Self::Worker => "worker",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = DBError;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "deploy" => Ok(Self::Deploy),
+            "invoke" => Ok(Self::Invoke),
+            "worker" => Ok(Self::Worker),
+            "admin" => Ok(Self::Admin),
+            other => Err(DBError::UnsupportedType(format!("scope {other}"))),
+        }
+    }
+}
+
+#[derive(Selectable, Queryable, Identifiable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: i32,
+    pub created_at: SystemTime,
+    pub tenant: String,
+    pub key_hash: String,
+    pub scopes: Vec<Option<String>>,
+    pub revoked: bool,
+}
+
+impl ApiKey {
+    /// Generates a fresh key for `tenant`, returns the plain key
+    /// alongside the insertable record, only the hash is persisted.
+    pub fn generate(tenant: String, scopes: &[Scope]) -> (String, NewApiKey) {
+        let key: String = rand::random::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let instance = NewApiKey {
+            tenant,
+            key_hash: Self::hash(&key),
+            scopes: scopes.iter().map(|s| Some(s.as_str().to_owned())).collect(),
+        };
+        (key, instance)
+    }
+
+    pub fn hash(key: &str) -> String {
+        format!("{:x}", Sha256::digest(key.as_bytes()))
+    }
+
+    pub async fn find(key: &str, conn: &mut DBPoolConnection) -> Result<Self, DBError> {
+        api_keys::table
+            .filter(api_keys::key_hash.eq(Self::hash(key)))
+            .filter(api_keys::revoked.eq(false))
+            .select(ApiKey::as_select())
+            .get_result(conn)
+            .await
+            .map_err(DBError::from)
+    }
+
+    pub fn scopes(&self) -> Vec<Scope> {
+        self.scopes
+            .iter()
+            .flatten()
+            .filter_map(|s| s.parse().ok())
+            .collect()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    tenant: String,
+    key_hash: String,
+    scopes: Vec<Option<String>>,
+}
+
+impl NewApiKey {
+    pub async fn insert(&self, db_conn: &mut DBPoolConnection) -> Result<ApiKey, DBError> {
+        diesel::insert_into(api_keys::table)
+            .values(self)
+            .returning(ApiKey::as_returning())
+            .get_result(db_conn)
+            .await
+            .map_err(DBError::DBError)
+    }
+}
@@ -13,6 +13,8 @@ pub enum Error {
     MismatchedArgs,
     #[error("not a function")]
     NotAFunction,
+    #[error("quorum must be between 1 and the number of replicas")]
+    InvalidQuorum,
 }
 
 impl From<DieselError> for Error {
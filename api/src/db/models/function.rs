@@ -1,4 +1,4 @@
-use super::error::Error as DBError;
+use super::{error::Error as DBError, worker_reputation::TrustTier};
 use crate::db::{schema::functions, DBPoolConnection};
 use diesel::{
     deserialize::{self, FromSql, FromSqlRow},
@@ -9,9 +9,10 @@ use diesel::{
     sql_types::*,
 };
 use diesel_async::RunQueryDsl;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsValue;
-use std::{io::Write, time::SystemTime};
+use std::{io::Write, str::FromStr, time::SystemTime};
 
 #[derive(Selectable, Queryable, Identifiable)]
 #[diesel(table_name = functions)]
@@ -24,6 +25,24 @@ pub struct Function {
     pub uri: String,
     pub user_uri: String,
     pub signature: FunctionType,
+    pub tenant: Option<String>,
+    /// Safe to retry on another worker if the first one is lost.
+    pub idempotent: bool,
+    /// Distinct workers each invocation is dispatched to.
+    pub replicas: i32,
+    /// Matching results required out of `replicas`.
+    pub quorum: i32,
+    /// Least trusted tier of workers allowed to run the function.
+    pub min_tier: String,
+}
+
+/// How invocations of a function are dispatched to workers.
+#[derive(Debug, Clone, Copy)]
+pub struct InvokePolicy {
+    pub idempotent: bool,
+    pub replicas: u32,
+    pub quorum: u32,
+    pub min_tier: TrustTier,
 }
 
 impl Function {
@@ -32,7 +51,12 @@ impl Function {
         uri: &'a str,
         user_uri: &'a str,
         description: &[u8],
+        tenant: &'a str,
+        policy: InvokePolicy,
     ) -> Result<NewFunction<'a>, DBError> {
+        if policy.quorum < 1 || policy.quorum > policy.replicas {
+            return Err(DBError::InvalidQuorum);
+        }
         let (arity, signature) = Self::describe(description)?;
         let instance = NewFunction {
             arity: arity as i32,
@@ -40,6 +64,11 @@ impl Function {
             uri,
             user_uri,
             signature,
+            tenant,
+            idempotent: policy.idempotent,
+            replicas: policy.replicas as i32,
+            quorum: policy.quorum as i32,
+            min_tier: policy.min_tier.as_str(),
         };
         Ok(instance)
     }
@@ -60,6 +89,32 @@ impl Function {
             .map_err(|e| DBError::DBError(e))
     }
 
+    /// Lists functions owned by `tenant`, or every function
+    /// when no tenant is given.
+    pub async fn list(
+        tenant: Option<&str>,
+        conn: &mut DBPoolConnection,
+    ) -> Result<Vec<Self>, DBError> {
+        let mut query = functions::table
+            .select(Function::as_select())
+            .order(functions::id)
+            .into_boxed();
+        if let Some(tenant) = tenant {
+            query = query.filter(functions::tenant.eq(tenant));
+        }
+        query.load(conn).await.map_err(DBError::DBError)
+    }
+
+    pub fn policy(&self) -> InvokePolicy {
+        InvokePolicy {
+            idempotent: self.idempotent,
+            replicas: self.replicas as u32,
+            quorum: self.quorum as u32,
+            // Column only ever holds tiers written by `new`
+            min_tier: self.min_tier.parse().unwrap_or(TrustTier::Trusted),
+        }
+    }
+
     pub fn validate_args(&self, args: &Vec<JsValue>) -> Result<(), DBError> {
         let args_arity = args.len() as i32;
         // TODO: validate arg types
@@ -78,6 +133,11 @@ pub struct NewFunction<'a> {
     uri: &'a str,
     user_uri: &'a str,
     signature: FunctionType,
+    tenant: &'a str,
+    idempotent: bool,
+    replicas: i32,
+    quorum: i32,
+    min_tier: &'static str,
 }
 
 impl<'a> NewFunction<'a> {
@@ -91,7 +151,7 @@ impl<'a> NewFunction<'a> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[repr(u8)]
 #[serde(rename_all = "snake_case", tag = "type", content = "content")]
 pub enum TypeDesc {
@@ -166,6 +226,88 @@ impl TypeDesc {
         };
         Ok(ty)
     }
+
+    /// Whether `value` is a valid JSON representation of this type,
+    /// as produced by workers. Booleans and chars may also come back
+    /// as their wasm integer representation, 64-bit integers as
+    /// decimal strings since JS numbers can't hold them past 2^53.
+    pub fn accepts(&self, value: &JsValue) -> bool {
+        let in_range =
+            |min: i64, max: i64| value.as_i64().is_some_and(|n| (min..=max).contains(&n));
+        match self {
+            Self::I8 => in_range(i8::MIN.into(), i8::MAX.into()),
+            Self::U8 | Self::ClampedU8 => in_range(0, u8::MAX.into()),
+            Self::I16 => in_range(i16::MIN.into(), i16::MAX.into()),
+            Self::U16 => in_range(0, u16::MAX.into()),
+            Self::I32 => in_range(i32::MIN.into(), i32::MAX.into()),
+            Self::U32 => in_range(0, u32::MAX.into()),
+            Self::I64 => value.is_i64() || parse_int::<i64>(value).is_some(),
+            Self::U64 => value.is_u64() || parse_int::<u64>(value).is_some(),
+            Self::F32 => value.as_f64().is_some_and(|n| n.abs() <= f32::MAX as f64),
+            Self::F64 => value.is_number(),
+            Self::Boolean => value.is_boolean() || in_range(0, 1),
+            Self::Char => match value {
+                JsValue::String(s) => s.chars().count() == 1,
+                _ => value
+                    .as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .and_then(char::from_u32)
+                    .is_some(),
+            },
+            Self::String | Self::CachedString => value.is_string(),
+            Self::Ref(inner) | Self::RefMut(inner) => inner.accepts(value),
+            Self::Slice(inner) | Self::Vector(inner) => value
+                .as_array()
+                .is_some_and(|items| items.iter().all(|i| inner.accepts(i))),
+            Self::Option(inner) => value.is_null() || inner.accepts(value),
+            Self::Unit => value.is_null(),
+            // Can't be represented in JSON
+            _ => false,
+        }
+    }
+
+    /// Canonical form of a `value` this type `accepts`, so results
+    /// encoded differently by different runtimes compare equal.
+    pub fn normalize(&self, value: &JsValue) -> JsValue {
+        match (self, value) {
+            (Self::I64, JsValue::String(_)) => {
+                parse_int::<i64>(value).map_or(JsValue::Null, Into::into)
+            }
+            (Self::U64, JsValue::String(_)) => {
+                parse_int::<u64>(value).map_or(JsValue::Null, Into::into)
+            }
+            (Self::Boolean, JsValue::Number(n)) => JsValue::Bool(n.as_i64() != Some(0)),
+            (Self::Char, JsValue::Number(n)) => n
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .and_then(char::from_u32)
+                .map_or(JsValue::Null, |c| c.to_string().into()),
+            (Self::F32, JsValue::Number(n)) => n
+                .as_f64()
+                .and_then(|n| serde_json::Number::from_f64(n as f32 as f64))
+                .map_or(JsValue::Null, JsValue::Number),
+            (Self::Ref(inner) | Self::RefMut(inner), _) => inner.normalize(value),
+            (Self::Slice(inner) | Self::Vector(inner), JsValue::Array(items)) => {
+                JsValue::Array(items.iter().map(|i| inner.normalize(i)).collect())
+            }
+            (Self::Option(inner), _) if !value.is_null() => inner.normalize(value),
+            _ => value.clone(),
+        }
+    }
+
+    /// Whether values of this type are raw bytes, i.e. `Vec<u8>` or `&[u8]`.
+    pub fn is_bytes(&self) -> bool {
+        match self {
+            Self::Vector(inner) | Self::Slice(inner) => matches!(**inner, Self::U8),
+            Self::Ref(inner) | Self::RefMut(inner) => inner.is_bytes(),
+            _ => false,
+        }
+    }
+}
+
+/// Integer sent as a decimal string.
+fn parse_int<T: FromStr>(value: &JsValue) -> Option<T> {
+    value.as_str()?.parse().ok()
 }
 
 impl ToSql<Jsonb, Pg> for FunctionType {
@@ -186,7 +328,7 @@ impl FromSql<Jsonb, Pg> for FunctionType {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, AsExpression, FromSqlRow)]
 #[diesel(sql_type = Jsonb)]
 pub struct FunctionType {
     params: Vec<TypeDesc>,
@@ -218,4 +360,38 @@ impl FunctionType {
     pub fn arity(&self) -> usize {
         self.params.len()
     }
+
+    pub fn params(&self) -> &[TypeDesc] {
+        &self.params
+    }
+
+    pub fn ret(&self) -> &TypeDesc {
+        &self.ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn accepts_64_bit_integers_as_decimal_strings() {
+        assert!(TypeDesc::U64.accepts(&json!(u64::MAX)));
+        assert!(TypeDesc::U64.accepts(&json!(u64::MAX.to_string())));
+        assert!(TypeDesc::I64.accepts(&json!(i64::MIN.to_string())));
+        assert!(!TypeDesc::I64.accepts(&json!("1.5")));
+        assert!(!TypeDesc::U64.accepts(&json!("-1")));
+        assert!(!TypeDesc::I64.accepts(&json!(1.5)));
+    }
+
+    #[test]
+    fn normalizes_64_bit_strings_to_numbers() {
+        let big = json!("9007199254740993");
+        assert_eq!(TypeDesc::I64.normalize(&big), json!(9007199254740993i64));
+        assert_eq!(
+            TypeDesc::U64.normalize(&big),
+            TypeDesc::U64.normalize(&json!(9007199254740993u64))
+        );
+    }
 }
@@ -0,0 +1,110 @@
+use super::{error::Error as DBError, InvokeRequest};
+use crate::db::{schema::invocation_logs, DBPoolConnection};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// Messages longer than this are truncated.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trace => "trace",
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Line a function logged or printed while being invoked.
+#[derive(Debug, Serialize, Selectable, Queryable)]
+#[diesel(table_name = invocation_logs)]
+pub struct InvocationLog {
+    pub created_at: SystemTime,
+    pub request_id: String,
+    /// Tells apart the workers replicated invocations ran on.
+    pub dispatch_id: String,
+    pub level: String,
+    pub message: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = invocation_logs)]
+pub struct NewInvocationLog {
+    pub request_id: String,
+    pub invocation_id: String,
+    pub dispatch_id: String,
+    pub level: String,
+    pub message: String,
+}
+
+impl InvocationLog {
+    /// Logs of `invocation`, oldest first.
+    pub async fn list(
+        invocation: &InvokeRequest,
+        conn: &mut DBPoolConnection,
+    ) -> Result<Vec<Self>, DBError> {
+        // Recorded before invocations had ids, their logs can't be told apart
+        let Some(invocation_id) = &invocation.invocation_id else {
+            return Ok(vec![]);
+        };
+        invocation_logs::table
+            .filter(invocation_logs::invocation_id.eq(invocation_id))
+            .order(invocation_logs::id.asc())
+            .select(InvocationLog::as_select())
+            .load(conn)
+            .await
+            .map_err(DBError::DBError)
+    }
+}
+
+impl NewInvocationLog {
+    pub fn new(
+        request_id: &str,
+        invocation_id: &str,
+        dispatch_id: &str,
+        level: LogLevel,
+        mut message: String,
+    ) -> Self {
+        if message.len() > MAX_MESSAGE_LEN {
+            let mut end = MAX_MESSAGE_LEN;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        Self {
+            request_id: request_id.to_owned(),
+            invocation_id: invocation_id.to_owned(),
+            dispatch_id: dispatch_id.to_owned(),
+            level: level.as_str().to_owned(),
+            message,
+        }
+    }
+
+    pub async fn insert(&self, conn: &mut DBPoolConnection) -> Result<(), DBError> {
+        diesel::insert_into(invocation_logs::table)
+            .values(self)
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(DBError::DBError)
+    }
+}
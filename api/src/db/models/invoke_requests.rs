@@ -1,5 +1,11 @@
 use super::error::Error as DBError;
-use crate::db::{schema::invoke_requests, DBPoolConnection};
+use crate::{
+    db::{
+        schema::{functions, invoke_requests},
+        DBPoolConnection,
+    },
+    request_id::RequestId,
+};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use serde::Serialize;
@@ -15,6 +21,11 @@ pub struct InvokeRequest {
     pub function_id: i32,
     pub user_addr: String,
     pub payload: Option<JsValue>,
+    pub attempts: i32,
+    /// Id of the HTTP request that made the invocation.
+    pub request_id: Option<String>,
+    /// Unique id of the invocation, its logs are stored under.
+    pub invocation_id: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -23,6 +34,8 @@ pub struct NewInvokeRequest {
     pub function_id: i32,
     pub user_addr: String,
     pub payload: Option<JsValue>,
+    pub request_id: Option<String>,
+    pub invocation_id: Option<String>,
 }
 
 impl InvokeRequest {
@@ -30,14 +43,51 @@ impl InvokeRequest {
         user_addr: String,
         function_id: i32,
         payload: Option<&impl Serialize>,
+        request_id: &RequestId,
+        invocation_id: &RequestId,
     ) -> NewInvokeRequest {
         let payload = payload.map(|p| serde_json::to_value(p).unwrap());
         NewInvokeRequest {
             user_addr,
             function_id,
             payload: payload,
+            request_id: Some(request_id.to_string()),
+            invocation_id: Some(invocation_id.to_string()),
         }
     }
+
+    pub async fn record_attempts(
+        &self,
+        attempts: u32,
+        db_conn: &mut DBPoolConnection,
+    ) -> Result<(), DBError> {
+        diesel::update(invoke_requests::table.find(self.id))
+            .set(invoke_requests::attempts.eq(attempts as i32))
+            .execute(db_conn)
+            .await
+            .map(|_| ())
+            .map_err(DBError::DBError)
+    }
+}
+
+impl InvokeRequest {
+    /// Invocation `id` of a function of `tenant`, or of any
+    /// function when `tenant` is `None`.
+    pub async fn find(
+        id: i32,
+        tenant: Option<&str>,
+        conn: &mut DBPoolConnection,
+    ) -> Result<Self, DBError> {
+        let mut query = invoke_requests::table
+            .inner_join(functions::table)
+            .filter(invoke_requests::id.eq(id))
+            .select(InvokeRequest::as_select())
+            .into_boxed();
+        if let Some(tenant) = tenant {
+            query = query.filter(functions::tenant.eq(tenant));
+        }
+        query.first(conn).await.map_err(DBError::from)
+    }
 }
 
 impl NewInvokeRequest {
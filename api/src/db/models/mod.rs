@@ -1,6 +1,14 @@
+mod api_key;
 pub mod error;
 mod function;
+mod invocation_log;
 mod invoke_requests;
+mod usage;
+mod worker_reputation;
 
-pub use function::{Function, FunctionType};
+pub use api_key::{ApiKey, Scope};
+pub use function::{Function, FunctionType, InvokePolicy, TypeDesc};
+pub use invocation_log::{InvocationLog, LogLevel, NewInvocationLog};
 pub use invoke_requests::InvokeRequest;
+pub use usage::{Usage, UsageKind};
+pub use worker_reputation::{TrustTier, WorkerReputation};
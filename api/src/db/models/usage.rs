@@ -0,0 +1,78 @@
+use super::error::Error as DBError;
+use crate::db::{schema::usage_quotas, DBPoolConnection};
+use diesel::{pg::upsert::excluded, prelude::*};
+use diesel_async::RunQueryDsl;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy)]
+pub enum UsageKind {
+    Invocation,
+    Deploy,
+}
+
+/// Daily usage counters of a tenant, rows are keyed by
+/// days since UNIX epoch in UTC.
+#[derive(Selectable, Queryable)]
+#[diesel(table_name = usage_quotas)]
+pub struct Usage {
+    pub invocations: i32,
+    pub deploys: i32,
+}
+
+impl Usage {
+    /// Increments today's counter of `kind` for `tenant` and
+    /// returns the updated usage.
+    pub async fn record(
+        tenant: &str,
+        kind: UsageKind,
+        conn: &mut DBPoolConnection,
+    ) -> Result<Self, DBError> {
+        let (invocations, deploys) = match kind {
+            UsageKind::Invocation => (1, 0),
+            UsageKind::Deploy => (0, 1),
+        };
+        diesel::insert_into(usage_quotas::table)
+            .values((
+                usage_quotas::tenant.eq(tenant),
+                usage_quotas::day.eq(Self::today()),
+                usage_quotas::invocations.eq(invocations),
+                usage_quotas::deploys.eq(deploys),
+            ))
+            .on_conflict((usage_quotas::tenant, usage_quotas::day))
+            .do_update()
+            .set((
+                usage_quotas::invocations
+                    .eq(usage_quotas::invocations + excluded(usage_quotas::invocations)),
+                usage_quotas::deploys.eq(usage_quotas::deploys + excluded(usage_quotas::deploys)),
+            ))
+            .returning(Usage::as_returning())
+            .get_result(conn)
+            .await
+            .map_err(DBError::DBError)
+    }
+
+    pub fn count(&self, kind: UsageKind) -> i32 {
+        match kind {
+            UsageKind::Invocation => self.invocations,
+            UsageKind::Deploy => self.deploys,
+        }
+    }
+
+    /// Time left until counters reset at UTC midnight.
+    pub fn until_reset() -> Duration {
+        let elapsed = Self::since_epoch().as_secs() % SECS_PER_DAY;
+        Duration::from_secs(SECS_PER_DAY - elapsed)
+    }
+
+    fn today() -> i32 {
+        (Self::since_epoch().as_secs() / SECS_PER_DAY) as i32
+    }
+
+    fn since_epoch() -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,119 @@
+use super::error::Error as DBError;
+use crate::db::{schema::worker_reputations, DBPoolConnection};
+use diesel::{prelude::*, upsert::excluded};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::SystemTime};
+
+/// How much workers are trusted with results, ordered from
+/// least to most trusted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustTier {
+    /// Connected without an API key, e.g. browser workers.
+    #[default]
+    Anonymous,
+    /// Connected with a key holding the worker scope.
+    SemiTrusted,
+    /// Operated by admins, e.g. native workers.
+    Trusted,
+}
+
+impl TrustTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Anonymous => "anonymous",
+            Self::SemiTrusted => "semi_trusted",
+            Self::Trusted => "trusted",
+        }
+    }
+}
+
+impl FromStr for TrustTier {
+    type Err = DBError;
+
+    fn from_str(tier: &str) -> Result<Self, Self::Err> {
+        match tier {
+            "anonymous" => Ok(Self::Anonymous),
+            "semi_trusted" => Ok(Self::SemiTrusted),
+            "trusted" => Ok(Self::Trusted),
+            other => Err(DBError::UnsupportedType(format!("trust tier {other}"))),
+        }
+    }
+}
+
+/// Track record of a worker identity, kept across reconnects.
+#[derive(Debug, Clone, Selectable, Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = worker_reputations, primary_key(identity))]
+pub struct WorkerReputation {
+    pub identity: String,
+    pub updated_at: SystemTime,
+    pub tier: String,
+    pub successes: i64,
+    pub timeouts: i64,
+    pub malformed: i64,
+    pub disagreements: i64,
+    pub latency_ms: f64,
+}
+
+impl WorkerReputation {
+    pub fn new(identity: String, tier: TrustTier) -> Self {
+        Self {
+            identity,
+            updated_at: SystemTime::now(),
+            tier: tier.as_str().to_owned(),
+            successes: 0,
+            timeouts: 0,
+            malformed: 0,
+            disagreements: 0,
+            latency_ms: 0.0,
+        }
+    }
+
+    /// Loads the record of `identity`, starting a fresh one
+    /// for identities never seen before.
+    pub async fn load(
+        identity: String,
+        tier: TrustTier,
+        conn: &mut DBPoolConnection,
+    ) -> Result<Self, DBError> {
+        let found = worker_reputations::table
+            .find(&identity)
+            .select(WorkerReputation::as_select())
+            .get_result(conn)
+            .await;
+        match found {
+            Ok(mut record) => {
+                record.tier = tier.as_str().to_owned();
+                Ok(record)
+            }
+            Err(diesel::result::Error::NotFound) => Ok(Self::new(identity, tier)),
+            Err(e) => Err(DBError::DBError(e)),
+        }
+    }
+
+    /// Adds the counters of `self` to the persisted ones, so workers
+    /// sharing the identity don't overwrite each other's record.
+    pub async fn save(&self, conn: &mut DBPoolConnection) -> Result<(), DBError> {
+        use worker_reputations::{
+            disagreements, latency_ms, malformed, successes, tier, timeouts, updated_at,
+        };
+        diesel::insert_into(worker_reputations::table)
+            .values(self)
+            .on_conflict(worker_reputations::identity)
+            .do_update()
+            .set((
+                updated_at.eq(excluded(updated_at)),
+                tier.eq(excluded(tier)),
+                successes.eq(successes + excluded(successes)),
+                timeouts.eq(timeouts + excluded(timeouts)),
+                malformed.eq(malformed + excluded(malformed)),
+                disagreements.eq(disagreements + excluded(disagreements)),
+                latency_ms.eq(excluded(latency_ms)),
+            ))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(DBError::DBError)
+    }
+}
@@ -1,5 +1,16 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        tenant -> Varchar,
+        key_hash -> Varchar,
+        scopes -> Array<Nullable<Text>>,
+        revoked -> Bool,
+    }
+}
+
 diesel::table! {
     functions (id) {
         id -> Int4,
@@ -10,6 +21,23 @@ diesel::table! {
         uri -> Varchar,
         user_uri -> Varchar,
         signature -> Jsonb,
+        tenant -> Nullable<Varchar>,
+        idempotent -> Bool,
+        replicas -> Int4,
+        quorum -> Int4,
+        min_tier -> Varchar,
+    }
+}
+
+diesel::table! {
+    invocation_logs (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        request_id -> Varchar,
+        dispatch_id -> Varchar,
+        level -> Varchar,
+        message -> Text,
+        invocation_id -> Nullable<Varchar>,
     }
 }
 
@@ -20,12 +48,41 @@ diesel::table! {
         function_id -> Int4,
         user_addr -> Varchar,
         payload -> Nullable<Jsonb>,
+        attempts -> Int4,
+        request_id -> Nullable<Varchar>,
+        invocation_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    usage_quotas (tenant, day) {
+        tenant -> Varchar,
+        day -> Int4,
+        invocations -> Int4,
+        deploys -> Int4,
+    }
+}
+
+diesel::table! {
+    worker_reputations (identity) {
+        identity -> Varchar,
+        updated_at -> Timestamp,
+        tier -> Varchar,
+        successes -> Int8,
+        timeouts -> Int8,
+        malformed -> Int8,
+        disagreements -> Int8,
+        latency_ms -> Float8,
     }
 }
 
 diesel::joinable!(invoke_requests -> functions (function_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
     functions,
+    invocation_logs,
     invoke_requests,
+    usage_quotas,
+    worker_reputations,
 );
@@ -1,5 +1,9 @@
 use crate::util::{
     compiler::Compiler,
+    events::EventBus,
+    limits::Limiter,
+    metrics::Metrics,
+    spot_check::SpotChecker,
     storage::{self, Storage},
 };
 use crate::{registry::Registry, Settings};
@@ -11,18 +15,32 @@ pub struct Handles {
     pub storage: Arc<dyn Storage + Sync + Send>,
     pub registry: Arc<Registry>,
     pub compiler: Arc<Compiler>,
+    pub limiter: Arc<Limiter>,
+    pub spot_checker: Arc<SpotChecker>,
+    pub metrics: Arc<Metrics>,
+    pub events: Arc<EventBus>,
 }
 
 impl Handles {
     pub fn new(settings: &Settings) -> Result<Self, Box<dyn Error>> {
         let registry = Registry::start(&settings.registry);
         let compiler = Compiler::new(&settings.compiler.source_dir);
-        let storage = storage::init(&settings.storage);
+        let metrics = Arc::new(Metrics::new()?);
+        let storage: Arc<dyn Storage + Sync + Send> = Arc::new(storage::Metered::new(
+            storage::init(&settings.storage),
+            metrics.clone(),
+        ));
+        let limiter = Limiter::new(&settings.limits);
+        let spot_checker = SpotChecker::new(&settings.spot_check, storage.clone());
 
         let instance = Self {
-            storage: Arc::new(storage),
+            storage,
             registry: Arc::new(registry),
             compiler: Arc::new(compiler),
+            limiter: Arc::new(limiter),
+            spot_checker: Arc::new(spot_checker),
+            metrics,
+            events: Arc::new(EventBus::new()),
         };
         Ok(instance)
     }
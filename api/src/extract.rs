@@ -1,16 +1,22 @@
-use crate::status::{Status, StatusKind};
+use crate::{
+    db::models::{ApiKey, Function, Scope},
+    db::models::error::Error as DBError,
+    state::AppState,
+    status::{Status, StatusKind},
+};
 use axum::{
     body::Bytes,
     extract::{ConnectInfo, FromRequest, FromRequestParts},
     http::{
-        header::{CONTENT_TYPE},
+        header::{AUTHORIZATION, CONTENT_TYPE},
         request::Parts,
-        Request
+        HeaderMap, Request
     },
     response::{IntoResponse, Response},
     Json,
 };
-use std::net::{SocketAddr};
+use std::net::{IpAddr, SocketAddr};
+use subtle::ConstantTimeEq;
 
 pub struct WasmRepr<T>(pub T);
 
@@ -65,6 +71,10 @@ where
     }
 }
 
+/// Address of the client. Requests relayed by a proxy on the private
+/// network, e.g. Envoy, are attributed to the last `X-Forwarded-For`
+/// hop, the one the proxy appended. Earlier hops, and the header of
+/// requests made directly, are set by the client and ignored.
 pub struct RemoteAddress(pub String);
 
 #[axum::async_trait]
@@ -75,24 +85,151 @@ where
     type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let maybe_addr = parts
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            let msg = "Unrecognized remote host".to_owned();
+            return Err(Status::new(StatusKind::BadRequest, msg).into_response());
+        };
+        let peer = peer.ip();
+        let forwarded = if is_private(peer) {
+            forwarded_for(&parts.headers)
+        } else {
+            None
+        };
+        Ok(RemoteAddress(forwarded.unwrap_or(peer).to_string()))
+    }
+}
+
+/// Last hop of the `X-Forwarded-For` header.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let header = headers.get("X-Forwarded-For")?.to_str().ok()?;
+    header.rsplit(',').next()?.trim().parse().ok()
+}
+
+fn is_private(addr: IpAddr) -> bool {
+    match addr.to_canonical() {
+        IpAddr::V4(addr) => addr.is_private() || addr.is_loopback(),
+        IpAddr::V6(addr) => addr.is_loopback(),
+    }
+}
+
+/// Tenant resolved from the `Authorization: Bearer <key>` header.
+pub struct Tenant {
+    pub name: String,
+    /// Unset for the bootstrap admin key.
+    pub key_id: Option<i32>,
+    scopes: Vec<Scope>,
+}
+
+impl Tenant {
+    const ADMIN: &'static str = "admin";
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    /// Bucket the API key is rate limited under, the admin key is
+    /// only limited by remote address.
+    pub fn limit_key(&self) -> Option<String> {
+        self.key_id.map(|id| format!("key:{id}"))
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.scopes.contains(&Scope::Admin)
+    }
+
+    /// Admins may access every function, tenants only their own.
+    pub fn owns(&self, func: &Function) -> bool {
+        self.is_admin() || func.tenant.as_deref() == Some(self.name.as_str())
+    }
+}
+
+/// Tenant of a request the API key is optional for, unlike
+/// `Option<Tenant>` requests with an invalid key are rejected.
+pub struct MaybeTenant(pub Option<Tenant>);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for MaybeTenant {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            return Ok(Self(None));
+        }
+        let tenant = Tenant::from_request_parts(parts, state).await?;
+        Ok(Self(Some(tenant)))
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let unauthorized =
+            |msg: &str| Status::new(StatusKind::Unauthorized, msg.to_owned()).into_response();
+        let key = parts
             .headers
-            .get("X-Forwarded-For")
-            .and_then(|v| v.to_str().ok());
-        match maybe_addr {
-            Some(addr) => {
-                Ok(RemoteAddress(addr.to_owned()))
-            }
-            None => match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
-                Some(socket_addr) => {
-                    let addr = format!("{}", socket_addr.ip());
-                    Ok(RemoteAddress(addr))
-                }
-                None => {
-                    let msg = format!("Unrecognized remote host");
-                    Err(Status::new(StatusKind::BadRequest, msg).into_response())
-                }
-            },
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| unauthorized("API key must be provided"))?;
+
+        let is_admin_key = state
+            .admin_key
+            .as_deref()
+            .is_some_and(|admin| bool::from(admin.as_bytes().ct_eq(key.as_bytes())));
+        if is_admin_key {
+            return Ok(Tenant {
+                name: Self::ADMIN.to_owned(),
+                key_id: None,
+                scopes: vec![Scope::Admin],
+            });
+        }
+
+        let mut conn = state
+            .get_db_conn()
+            .await
+            .map_err(|e| Status::error(e.to_string()).into_response())?;
+        match ApiKey::find(key, &mut conn).await {
+            Ok(api_key) => Ok(Tenant {
+                key_id: Some(api_key.id),
+                scopes: api_key.scopes(),
+                name: api_key.tenant,
+            }),
+            Err(DBError::NotFound) => Err(unauthorized("invalid API key")),
+            Err(e) => Err(Status::error(e.to_string()).into_response()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn only_the_hop_appended_by_the_proxy_is_trusted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            HeaderValue::from_static("1.2.3.4, 10.0.0.1,203.0.113.7"),
+        );
+        assert_eq!(forwarded_for(&headers), "203.0.113.7".parse().ok());
+        headers.insert(
+            "X-Forwarded-For",
+            HeaderValue::from_static("1.2.3.4, bogus"),
+        );
+        assert_eq!(forwarded_for(&headers), None);
+
+        assert!(is_private("172.18.0.5".parse().unwrap()));
+        assert!(is_private("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!is_private("203.0.113.7".parse().unwrap()));
+    }
+}
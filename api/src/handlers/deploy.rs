@@ -1,24 +1,50 @@
 use super::result::{APIError, APIResult};
 use crate::{
-    db::models::Function, extensions::Handles, extract::WasmRepr, state::AppState,
-    status::Status, util::wasm::extract_description,
+    db::models::{Function, InvokePolicy, Scope, TrustTier, UsageKind},
+    extensions::Handles,
+    extract::{Tenant, WasmRepr},
+    state::AppState,
+    status::Status,
+    util::wasm::extract_description,
 };
 use axum::{
     body::Bytes,
-    extract::{Extension, State}
+    extract::{Extension, Query, State}
 };
 use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 
 #[axum::debug_handler]
 pub async fn deploy(
     handles: Extension<Handles>,
     state: State<AppState>,
+    tenant: Tenant,
+    Query(options): Query<DeployOptions>,
     WasmRepr(func): WasmRepr<DeployableFunction>,
 ) -> APIResult {
+    if !tenant.has_scope(Scope::Deploy) {
+        return Err(APIError::Forbidden);
+    }
+    if let Some(key) = tenant.limit_key() {
+        handles.limiter.throttle(&key).await?;
+    }
+    let mut db_conn = state
+        .get_db_conn()
+        .await
+        .map_err(|_| APIError::InternalError)?;
+    handles
+        .limiter
+        .consume_quota(&tenant.name, UsageKind::Deploy, &mut db_conn)
+        .await?;
     let bytes = match func {
         DeployableFunction::Body(code) => {
             tracing::trace!("Deploying function");
-            let bytes = handles.compiler.compile(&code).await?;
+            let started = Instant::now();
+            let compiled = handles.compiler.compile(&code).await;
+            handles
+                .metrics
+                .observe_compile(compiled.is_ok(), started.elapsed());
+            let bytes = compiled?;
             tracing::trace!("Compiled function with size: {} bytes", bytes.len());
             bytes
         }
@@ -40,20 +66,49 @@ pub async fn deploy(
         .map_err(|_| APIError::InternalError)?;
     // Create a function record in DB
     tracing::debug!("Writing function to DB");
-    let mut db_conn = state
-        .get_db_conn()
-        .await
-        .map_err(|_| APIError::InternalError)?;
     let user_uri = format!("assets/{}.wasm", filename);
-    let func = Function::new(&name, &path, &user_uri, &description)?
-        .insert(&mut db_conn)
-        .await
-        .map_err(|_| APIError::InternalError)?;
+    let func = Function::new(
+        &name,
+        &path,
+        &user_uri,
+        &description,
+        &tenant.name,
+        options.policy(),
+    )?
+    .insert(&mut db_conn)
+    .await
+    .map_err(|_| APIError::InternalError)?;
     let response = Deployment::new(func);
     tracing::trace!("Successfuly deployed function");
     Ok(Status::ok_payload(response))
 }
 
+#[derive(Deserialize)]
+pub struct DeployOptions {
+    /// Allows retrying invocations on another worker.
+    #[serde(default)]
+    idempotent: bool,
+    /// Dispatches each invocation to this many distinct workers.
+    replicas: Option<u32>,
+    /// Matching results required out of `replicas`, defaults to all.
+    quorum: Option<u32>,
+    /// Least trusted workers allowed to run the function.
+    #[serde(default)]
+    min_tier: TrustTier,
+}
+
+impl DeployOptions {
+    fn policy(&self) -> InvokePolicy {
+        let replicas = self.replicas.unwrap_or(1);
+        InvokePolicy {
+            idempotent: self.idempotent,
+            replicas,
+            quorum: self.quorum.unwrap_or(replicas),
+            min_tier: self.min_tier,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all="snake_case")]
 pub enum DeployableFunction {
@@ -74,18 +129,26 @@ impl From<String> for DeployableFunction {
 }
 
 #[derive(Serialize)]
-struct Deployment {
+pub(super) struct Deployment {
     id: i32,
     name: String,
     uri: String,
+    idempotent: bool,
+    replicas: i32,
+    quorum: i32,
+    min_tier: String,
 }
 
 impl Deployment {
-    fn new(func: Function) -> Self {
+    pub(super) fn new(func: Function) -> Self {
         Self {
             id: func.id,
             name: func.name,
             uri: func.user_uri,
+            idempotent: func.idempotent,
+            replicas: func.replicas,
+            quorum: func.quorum,
+            min_tier: func.min_tier,
         }
     }
 }
@@ -0,0 +1,78 @@
+use super::result::APIError;
+use crate::{
+    db::models::{Function, LogLevel},
+    extensions::Handles,
+    extract::Tenant,
+    state::AppState,
+    util::events::Event,
+};
+use axum::{
+    extract::{Extension, Path, Query, State},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+};
+use futures::stream::{self, Stream, StreamExt};
+use serde::Deserialize;
+use std::future::ready;
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Deserialize)]
+pub struct StreamFilter {
+    /// Least severe level streamed.
+    level: Option<LogLevel>,
+    /// Only events of this invocation, as in the
+    /// `X-Invocation-Id` header of the invoke response.
+    invocation_id: Option<i32>,
+}
+
+impl StreamFilter {
+    fn matches(&self, function_id: i32, event: &Event) -> bool {
+        event.function_id() == function_id
+            && self.level.is_none_or(|level| event.level() >= level)
+            && self
+                .invocation_id
+                .is_none_or(|id| event.invocation_id() == id)
+    }
+}
+
+/// Streams invocation events and function logs of a
+/// function as they happen, as Server-Sent Events.
+pub async fn stream(
+    Extension(handles): Extension<Handles>,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    tenant: Tenant,
+    Query(filter): Query<StreamFilter>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, serde_json::Error>>>, APIError> {
+    let mut db_conn = state
+        .get_db_conn()
+        .await
+        .map_err(|_| APIError::InternalError)?;
+    let func = Function::get(id, &mut db_conn).await?;
+    if !tenant.owns(&func) {
+        return Err(APIError::Forbidden);
+    }
+    // Not needed while streaming
+    drop(db_conn);
+
+    let receiver = handles.events.subscribe();
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((Ok(event), receiver)),
+            Err(RecvError::Lagged(skipped)) => Some((Err(skipped), receiver)),
+            Err(RecvError::Closed) => None,
+        }
+    })
+    .filter(move |event| match event {
+        Ok(event) => ready(filter.matches(func.id, event)),
+        Err(_) => ready(true),
+    })
+    .map(|event| match event {
+        Ok(event) => SseEvent::default().event(event.name()).json_data(&event),
+        // Subscriber was too slow, tell it events were missed
+        Err(skipped) => Ok(SseEvent::default()
+            .event("lagged")
+            .data(skipped.to_string())),
+    })
+    .take_until(handles.events.closed());
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,20 @@
+use super::{
+    deploy::Deployment,
+    result::{APIError, APIResult},
+};
+use crate::{db::models::Function, extract::Tenant, state::AppState, status::Status};
+use axum::extract::State;
+
+pub async fn list(State(state): State<AppState>, tenant: Tenant) -> APIResult {
+    let mut db_conn = state
+        .get_db_conn()
+        .await
+        .map_err(|_| APIError::InternalError)?;
+    let owner = (!tenant.is_admin()).then_some(tenant.name.as_str());
+    let functions: Vec<Deployment> = Function::list(owner, &mut db_conn)
+        .await?
+        .into_iter()
+        .map(Deployment::new)
+        .collect();
+    Ok(Status::ok_payload(functions))
+}
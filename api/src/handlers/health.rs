@@ -0,0 +1,95 @@
+use crate::{
+    extensions::Handles,
+    state::AppState,
+    status::{Status, StatusKind},
+};
+use axum::extract::{Extension, State};
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use std::future::Future;
+use tokio::time::Duration;
+
+/// Dependency checks taking longer fail.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    async fn run(check: impl Future<Output = Result<(), String>>) -> Self {
+        let result = tokio::time::timeout(CHECK_TIMEOUT, check)
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_owned()));
+        Self {
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WorkersCheck {
+    ok: bool,
+    available: usize,
+    required: usize,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    db: Check,
+    storage: Check,
+    compiler: Check,
+    workers: WorkersCheck,
+}
+
+/// The process is up and serving requests.
+pub async fn healthz() -> Status {
+    Status::ok()
+}
+
+/// Whether the API's dependencies are usable, with a breakdown
+/// of each check.
+pub async fn readyz(
+    Extension(handles): Extension<Handles>,
+    State(state): State<AppState>,
+) -> Status {
+    let (db, storage, compiler) = tokio::join!(
+        Check::run(check_db(&state)),
+        Check::run(async { handles.storage.check().await.map_err(|e| e.to_string()) }),
+        Check::run(async { handles.compiler.check().await.map_err(|e| e.to_string()) }),
+    );
+    let (available, required) = handles.registry.readiness().await;
+    let workers = WorkersCheck {
+        ok: available >= required,
+        available,
+        required,
+    };
+    let ready = db.ok && storage.ok && compiler.ok && workers.ok;
+    let kind = if ready {
+        StatusKind::Ok
+    } else {
+        StatusKind::ServiceUnavailable
+    };
+    let readiness = Readiness {
+        ready,
+        db,
+        storage,
+        compiler,
+        workers,
+    };
+    Status::new(kind, readiness)
+}
+
+async fn check_db(state: &AppState) -> Result<(), String> {
+    let mut conn = state.get_db_conn().await.map_err(|e| e.to_string())?;
+    diesel::sql_query("SELECT 1")
+        .execute(&mut conn)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,22 @@
+use super::result::{APIError, APIResult};
+use crate::{
+    db::models::{InvocationLog, InvokeRequest},
+    extract::Tenant,
+    state::AppState,
+    status::Status,
+};
+use axum::extract::{Path, State};
+
+/// Lists what the function logged while handling invocation `id`,
+/// as returned in the `X-Invocation-Id` header of the invoke response.
+pub async fn logs(State(state): State<AppState>, Path(id): Path<i32>, tenant: Tenant) -> APIResult {
+    let mut db_conn = state
+        .get_db_conn()
+        .await
+        .map_err(|_| APIError::InternalError)?;
+    // Invocations of other tenants' functions are reported missing
+    let owner = (!tenant.is_admin()).then_some(tenant.name.as_str());
+    let invocation = InvokeRequest::find(id, owner, &mut db_conn).await?;
+    let logs = InvocationLog::list(&invocation, &mut db_conn).await?;
+    Ok(Status::ok_payload(logs))
+}
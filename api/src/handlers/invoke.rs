@@ -1,36 +1,159 @@
-use super::result::APIResult;
+use super::result::{APIError, APIResult};
 use crate::{
-    db::models::{Function, InvokeRequest},
+    db::{
+        models::{error::Error as DBError, Function, InvokeRequest, Scope, UsageKind},
+        DBPoolConnection,
+    },
     extensions::Handles,
-    registry::BackendError,
+    request_id::RequestId,
     state::AppState,
-    extract::RemoteAddress,
+    status::Status,
+    extract::{RemoteAddress, Tenant},
+    util::{limits::LimitError, metrics::UNRESOLVED_FUNCTION},
 };
 use axum::{
-    extract::{Extension, Path, State},
+    extract::{rejection::JsonRejection, Extension, Path, State},
+    http::{HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
     Json,
 };
 use serde::Deserialize;
 use serde_json::Value as JsValue;
+use tokio::time::Instant;
 
 pub async fn invoke(
     Extension(handles): Extension<Handles>,
     State(state): State<AppState>,
     Path(id): Path<i32>,
     RemoteAddress(addr): RemoteAddress,
-    Json(request): Json<UserInvokeRequest>,
-) -> APIResult {
+    Extension(request_id): Extension<RequestId>,
+    tenant: Result<Tenant, Response>,
+    request: Result<Json<UserInvokeRequest>, JsonRejection>,
+) -> Response {
+    let metrics = handles.metrics.clone();
+    let tenant = match tenant {
+        Ok(tenant) => tenant,
+        Err(rejection) => {
+            metrics.observe_rejection(UNRESOLVED_FUNCTION, "unauthorized");
+            return rejection;
+        }
+    };
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            metrics.observe_rejection(UNRESOLVED_FUNCTION, "malformed_request");
+            return rejection.into_response();
+        }
+    };
+    let recorded = record_invocation(&handles, &state, id, addr, &request_id, &tenant, &request);
+    let recorded = match recorded.await {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            if let Some((outcome, resolved)) = rejection(&e) {
+                let id = id.to_string();
+                let function = if resolved { &id } else { UNRESOLVED_FUNCTION };
+                metrics.observe_rejection(function, outcome);
+            }
+            return e.into_response();
+        }
+    };
+    let record_id = recorded.record.id;
+    let invoked = invoke_function(&handles, recorded, &request_id, request).await;
+    let mut response = invoked.into_response();
+    response
+        .headers_mut()
+        .insert(X_INVOCATION_ID.clone(), HeaderValue::from(record_id));
+    response
+}
+
+/// Id of the record of an invocation, its logs are listed by.
+pub static X_INVOCATION_ID: HeaderName = HeaderName::from_static("x-invocation-id");
+
+/// Outcome label of an invocation turned away before it was dispatched,
+/// along with whether its function was resolved by then.
+fn rejection(error: &APIError) -> Option<(&'static str, bool)> {
+    let rejection = match error {
+        // Counted when dispatched
+        APIError::RegistryError(_) => return None,
+        APIError::Forbidden => ("forbidden", false),
+        APIError::DBError(DBError::NotFound) => ("not_found", false),
+        APIError::LimitError(LimitError::RateLimited(_)) => ("rate_limited", false),
+        APIError::DBError(DBError::MismatchedArgs) => ("bad_arguments", true),
+        APIError::LimitError(LimitError::QuotaExceeded(_)) => ("quota_exceeded", true),
+        _ => ("internal_error", false),
+    };
+    Some(rejection)
+}
+
+/// Invocation allowed and recorded, ready to be dispatched.
+struct Recorded {
+    func: Function,
+    record: InvokeRequest,
+    invocation_id: RequestId,
+    db_conn: DBPoolConnection,
+}
+
+/// Checks the invocation is allowed and records it.
+async fn record_invocation(
+    handles: &Handles,
+    state: &AppState,
+    id: i32,
+    addr: String,
+    request_id: &RequestId,
+    tenant: &Tenant,
+    request: &UserInvokeRequest,
+) -> Result<Recorded, APIError> {
+    if !tenant.has_scope(Scope::Invoke) {
+        return Err(APIError::Forbidden);
+    }
+    if let Some(key) = tenant.limit_key() {
+        handles.limiter.throttle(&key).await?;
+    }
     let mut db_conn = state
         .get_db_conn()
         .await
-        .map_err(|_| BackendError::NoReply)?;
+        .map_err(|_| APIError::InternalError)?;
     // Fetch function
     let func = Function::get(id, &mut db_conn).await?;
+    if !tenant.owns(&func) {
+        return Err(APIError::Forbidden);
+    }
     func.validate_args(&request.args)?;
-    // Record request
-    InvokeRequest::new(addr, func.id, Some(&request.args))
-        .insert(&mut db_conn)
+    handles
+        .limiter
+        .consume_quota(&tenant.name, UsageKind::Invocation, &mut db_conn)
         .await?;
+    // Record request
+    let invocation_id = request_id.invocation_id();
+    let record = InvokeRequest::new(
+        addr,
+        func.id,
+        Some(&request.args),
+        request_id,
+        &invocation_id,
+    )
+    .insert(&mut db_conn)
+    .await?;
+    Ok(Recorded {
+        func,
+        record,
+        invocation_id,
+        db_conn,
+    })
+}
+
+async fn invoke_function(
+    handles: &Handles,
+    recorded: Recorded,
+    request_id: &RequestId,
+    request: UserInvokeRequest,
+) -> APIResult {
+    let Recorded {
+        func,
+        record,
+        invocation_id,
+        mut db_conn,
+    } = recorded;
     tracing::trace!(
         "Invoking function: {} with args: {:?}",
         &func.name,
@@ -38,9 +161,46 @@ pub async fn invoke(
     );
     let registry = &handles.registry;
     tracing::trace!("Dispatching invocation request to worker registry");
-    Ok(registry
-        .invoke(func.name, func.user_uri, func.signature, request.args)
-        .await?)
+    let policy = func.policy();
+    let events = handles
+        .events
+        .started(func.id, record.id, request_id, &invocation_id);
+    let started = Instant::now();
+    let invocation = registry
+        .invoke(
+            &invocation_id,
+            &func.name,
+            &func.user_uri,
+            &func.signature,
+            &request.args,
+            policy,
+        )
+        .await;
+    let outcome = invocation
+        .result
+        .as_ref()
+        .map_or_else(|e| e.kind(), |_| "ok");
+    handles
+        .metrics
+        .observe_invocation(&func.id.to_string(), outcome, started.elapsed());
+    let error = invocation.result.as_ref().err().map(|e| e.to_string());
+    events.finish(outcome, error);
+    if let Err(e) = record
+        .record_attempts(invocation.attempts, &mut db_conn)
+        .await
+    {
+        tracing::error!("Failed to record attempts of request {}: {}", record.id, e);
+    }
+    let value = handles
+        .spot_checker
+        .check(
+            &func,
+            &request.args,
+            invocation.result?,
+            &invocation.workers,
+        )
+        .await;
+    Ok(Status::ok_payload(value))
 }
 
 #[derive(Deserialize)]
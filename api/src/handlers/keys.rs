@@ -0,0 +1,52 @@
+use super::result::{APIError, APIResult};
+use crate::{
+    db::models::{ApiKey, Scope},
+    extract::Tenant,
+    state::AppState,
+    status::Status,
+};
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+
+/// Issues a new API key, the plain key is only ever returned here.
+pub async fn create(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Json(request): Json<NewKeyRequest>,
+) -> APIResult {
+    if !tenant.is_admin() {
+        return Err(APIError::Forbidden);
+    }
+    let mut db_conn = state
+        .get_db_conn()
+        .await
+        .map_err(|_| APIError::InternalError)?;
+    let (key, record) = ApiKey::generate(request.tenant, &request.scopes);
+    let record = record.insert(&mut db_conn).await?;
+    tracing::debug!(
+        "Issued API key ({}) for tenant: {}",
+        record.id,
+        record.tenant
+    );
+    let response = IssuedKey {
+        id: record.id,
+        scopes: record.scopes(),
+        tenant: record.tenant,
+        key,
+    };
+    Ok(Status::ok_payload(response))
+}
+
+#[derive(Deserialize)]
+pub struct NewKeyRequest {
+    tenant: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize)]
+struct IssuedKey {
+    id: i32,
+    tenant: String,
+    scopes: Vec<Scope>,
+    key: String,
+}
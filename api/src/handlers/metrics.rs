@@ -0,0 +1,20 @@
+use super::result::APIError;
+use crate::{extensions::Handles, state::AppState};
+use axum::{
+    extract::{Extension, State},
+    http::header,
+    response::IntoResponse,
+};
+
+/// Serves metrics in the Prometheus text format.
+pub async fn metrics(
+    Extension(handles): Extension<Handles>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, APIError> {
+    let workers = handles.registry.workers().await;
+    let body = handles
+        .metrics
+        .render(&workers, &state.pool)
+        .map_err(|_| APIError::InternalError)?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
@@ -1,7 +1,25 @@
 mod deploy;
+mod events;
+mod functions;
+mod health;
+mod invocations;
 mod invoke;
+mod keys;
+mod metrics;
+mod protocol;
 mod result;
+mod throttle;
+mod workers;
 mod ws;
 pub use deploy::deploy as DeployHandler;
+pub use events::stream as StreamEventsHandler;
+pub use functions::list as ListFunctionsHandler;
+pub use health::{healthz as HealthHandler, readyz as ReadinessHandler};
+pub use invocations::logs as InvocationLogsHandler;
 pub use invoke::invoke as InvokeHandler;
+pub use keys::create as CreateKeyHandler;
+pub use metrics::metrics as MetricsHandler;
+pub use protocol::schema as ProtocolSchemaHandler;
+pub use throttle::by_address as ThrottleMiddleware;
+pub use workers::{drain as DrainWorkerHandler, list as ListWorkersHandler};
 pub use ws::ws_handler as WSHandler;
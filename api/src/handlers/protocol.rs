@@ -0,0 +1,8 @@
+use crate::proto;
+use axum::Json;
+use schemars::schema::RootSchema;
+
+/// Serves the JSON Schema workers can validate messages against.
+pub async fn schema() -> Json<RootSchema> {
+    Json(proto::schema())
+}
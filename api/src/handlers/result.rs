@@ -1,8 +1,9 @@
 use crate::{
     db::models::error::Error as DBError,
+    proto::WorkerErrorKind,
     registry::BackendError,
     status::{Status, StatusKind},
-    util::{compiler::CompileError, wasm::WasmError},
+    util::{compiler::CompileError, limits::LimitError, wasm::WasmError},
 };
 use axum::response::{IntoResponse, Response};
 use thiserror::Error;
@@ -21,6 +22,10 @@ pub enum APIError {
     NotAFunction,
     #[error("{0}")]
     CompileError(#[from] CompileError),
+    #[error("{0}")]
+    LimitError(#[from] LimitError),
+    #[error("insufficient permissions")]
+    Forbidden,
     #[error("internal error")]
     InternalError,
 }
@@ -28,8 +33,38 @@ pub enum APIError {
 impl Into<Status> for APIError {
     fn into(self) -> Status {
         let msg = format!("{}", self);
+        if let Self::RegistryError(BackendError::Execution(e)) = &self {
+            let kind = match e.kind {
+                WorkerErrorKind::Trap | WorkerErrorKind::BadArguments => {
+                    StatusKind::UnprocessableEntity
+                }
+                WorkerErrorKind::Unsupported => StatusKind::BadRequest,
+                WorkerErrorKind::FetchFailed | WorkerErrorKind::Internal => StatusKind::BadGateway,
+            };
+            return Status::new(kind, e);
+        }
+        if let Self::LimitError(e) = &self {
+            if let Some(after) = e.retry_after() {
+                return Status::new(StatusKind::TooManyRequests, msg).with_retry_after(after);
+            }
+        }
         let kind = match self {
             Self::DBError(DBError::NotFound) => StatusKind::NotFound,
+            Self::RegistryError(BackendError::UnknownWorker) => StatusKind::NotFound,
+            Self::RegistryError(BackendError::InvalidResult | BackendError::NoQuorum) => {
+                StatusKind::BadGateway
+            }
+            Self::RegistryError(BackendError::NotEnoughWorkers(_)) => {
+                StatusKind::ServiceUnavailable
+            }
+            Self::LimitError(_) => StatusKind::InternalError,
+            Self::RegistryError(BackendError::Overloaded | BackendError::ShuttingDown) => {
+                StatusKind::ServiceUnavailable
+            }
+            Self::RegistryError(BackendError::Timeout | BackendError::DeadlineExceeded) => {
+                StatusKind::GatewayTimeout
+            }
+            Self::Forbidden => StatusKind::Forbidden,
             Self::InternalError => StatusKind::InternalError,
             _ => StatusKind::BadRequest,
         };
@@ -0,0 +1,34 @@
+use super::result::APIError;
+use crate::{extensions::Handles, extract::RemoteAddress, util::metrics::UNRESOLVED_FUNCTION};
+use axum::{
+    extract::{Extension, MatchedPath},
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Rate limits requests by remote address before they are
+/// authenticated, so invalid keys can't flood the API.
+pub async fn by_address<B>(
+    Extension(handles): Extension<Handles>,
+    RemoteAddress(addr): RemoteAddress,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match handles.limiter.throttle(&format!("addr:{addr}")).await {
+        Ok(()) => next.run(request).await,
+        Err(e) => {
+            if is_invocation(&request) {
+                handles
+                    .metrics
+                    .observe_rejection(UNRESOLVED_FUNCTION, "rate_limited");
+            }
+            APIError::from(e).into_response()
+        }
+    }
+}
+
+fn is_invocation<B>(request: &Request<B>) -> bool {
+    let route = request.extensions().get::<MatchedPath>();
+    request.method() == Method::POST && route.map(MatchedPath::as_str) == Some("/functions/:id")
+}
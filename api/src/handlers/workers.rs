@@ -0,0 +1,26 @@
+use super::result::{APIError, APIResult};
+use crate::{extensions::Handles, extract::Tenant, status::Status};
+use axum::extract::{Extension, Path};
+use uuid::Uuid;
+
+/// Lists connected workers.
+pub async fn list(Extension(handles): Extension<Handles>, tenant: Tenant) -> APIResult {
+    if !tenant.is_admin() {
+        return Err(APIError::Forbidden);
+    }
+    Ok(Status::ok_payload(handles.registry.workers().await))
+}
+
+/// Takes a worker out of rotation, it is disconnected once
+/// its in-flight requests are done.
+pub async fn drain(
+    Extension(handles): Extension<Handles>,
+    tenant: Tenant,
+    Path(worker_id): Path<Uuid>,
+) -> APIResult {
+    if !tenant.is_admin() {
+        return Err(APIError::Forbidden);
+    }
+    handles.registry.drain(&worker_id, true).await?;
+    Ok(Status::ok())
+}
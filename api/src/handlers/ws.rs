@@ -1,84 +1,318 @@
 use crate::{
+    db::models::{LogLevel, NewInvocationLog, Scope, TrustTier, WorkerReputation},
     extensions::Handles,
-    proto::{WorkerMsg, RegistryMsg, WSProto},
-    registry::Registry,
-    registry::RegistryHandle,
-    extract::RemoteAddress
+    proto::{self, Encoding, WorkerMsg, RegistryMsg, WSProto},
+    registry::{Outcome, Registry, RegistryHandle, Reputation, WorkerHealth, REPLY_GRACE},
+    request_id::RequestId,
+    extract::{MaybeTenant, RemoteAddress},
+    state::AppState,
+    status::{Status, StatusKind},
+    telemetry,
+    util::events::EventBus,
 };
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Extension,
+        Extension, Query, State,
     },
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
-use futures::{sink::SinkExt, stream::StreamExt};
-use futures_util::stream::{SplitSink, SplitStream};
+use futures::{
+    sink::{Sink, SinkExt},
+    stream::StreamExt,
+};
+use futures_util::stream::SplitStream;
+use serde::Deserialize;
 use std::{collections::HashMap, ops::ControlFlow, sync::Arc};
 use tokio::{
     sync::{oneshot::Sender, Mutex},
-    time::Duration,
+    time::{Duration, Instant, MissedTickBehavior},
 };
+use tracing::Instrument;
 use uuid::Uuid;
 
+/// Lines a worker may log per request, later ones are dropped.
+const MAX_LOG_LINES: usize = 200;
+
+struct PendingReply {
+    sender: Sender<RegistryMsg>,
+    deadline: Instant,
+    /// Lines the worker logged for the request so far.
+    logged: usize,
+}
+
+enum LogAdmission {
+    Accepted,
+    /// First line past `MAX_LOG_LINES`, replaced by a marker.
+    Truncated,
+    Dropped,
+    /// Log of a request the worker isn't executing.
+    Unknown,
+}
+
+enum ReplyError {
+    /// Reply to a request whose deadline already passed.
+    Late,
+    /// Reply to a request that was never sent to the worker.
+    Unknown,
+}
+
 /// Main purpose is to store oneshot channel
 /// senders that are used to reply back to registry.
 /// This pool is shared between both the worker_relay
 /// task and the registry_relay task.
 struct WSReplyPool {
-    senders: Mutex<HashMap<String, Sender<RegistryMsg>>>,
+    senders: Mutex<HashMap<String, PendingReply>>,
+    registry: Arc<Registry>,
 }
 
 impl WSReplyPool {
-    pub fn new() -> Self {
+    pub fn new(registry: Arc<Registry>) -> Self {
         Self {
             senders: Mutex::new(HashMap::new()),
+            registry,
+        }
+    }
+
+    pub async fn register(&self, id: String, sender: Sender<RegistryMsg>, deadline: Instant) {
+        let pending = PendingReply {
+            sender,
+            deadline,
+            logged: 0,
+        };
+        self.senders.lock().await.insert(id, pending);
+    }
+
+    pub async fn reply(&self, id: &str, reply: RegistryMsg) -> Result<(), ReplyError> {
+        let maybe_pending = self.senders.lock().await.remove(id);
+        match maybe_pending {
+            Some(pending) if pending.deadline < Instant::now() => {
+                self.registry.cancel(id);
+                let _ = pending.sender.send(RegistryMsg::Expired);
+                Err(ReplyError::Late)
+            }
+            // Caller gave up waiting
+            Some(pending) => pending.sender.send(reply).map_err(|_| ReplyError::Late),
+            None if self.registry.is_cancelled(id) => Err(ReplyError::Late),
+            None => Err(ReplyError::Unknown),
         }
     }
 
-    pub async fn register(
-        &self,
-        id: String,
-        sender: Sender<RegistryMsg>,
-    ) -> Option<Sender<RegistryMsg>> {
-        self.senders.lock().await.insert(id, sender)
+    /// Drops the sender of a request the registry cancelled.
+    pub async fn cancel(&self, id: &str) {
+        self.senders.lock().await.remove(id);
     }
 
-    pub async fn reply(&self, id: &str, reply: RegistryMsg) -> Result<(), RegistryMsg> {
-        let maybe_sender = self.senders.lock().await.remove(id);
-        match maybe_sender {
-            Some(sender) => sender.send(reply),
-            None => Err(reply),
+    /// Expires requests whose caller timed out or went away, so
+    /// entries of requests never replied to don't pile up.
+    pub async fn sweep(&self) {
+        let now = Instant::now();
+        let mut senders = self.senders.lock().await;
+        senders.retain(|id, pending| {
+            let overdue = pending.deadline + REPLY_GRACE < now || pending.sender.is_closed();
+            if overdue {
+                tracing::debug!("Expiring request {id}, worker didn't reply in time");
+                self.registry.cancel(id);
+            }
+            !overdue
+        });
+    }
+
+    /// Counts a line logged for request `id` against its cap.
+    pub async fn admit_log(&self, id: &str) -> LogAdmission {
+        let mut senders = self.senders.lock().await;
+        let Some(pending) = senders.get_mut(id) else {
+            return LogAdmission::Unknown;
+        };
+        pending.logged += 1;
+        match pending.logged {
+            n if n <= MAX_LOG_LINES => LogAdmission::Accepted,
+            n if n == MAX_LOG_LINES + 1 => LogAdmission::Truncated,
+            _ => LogAdmission::Dropped,
+        }
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.senders.lock().await.is_empty()
+    }
+
+    /// Fails every pending request, the worker is gone and will
+    /// never reply.
+    pub async fn disconnect(&self) {
+        let senders = std::mem::take(&mut *self.senders.lock().await);
+        for (_, pending) in senders {
+            let _ = pending.sender.send(RegistryMsg::Disconnected);
         }
     }
 }
 
+/// Parameters advertised by workers when connecting.
+#[derive(Deserialize)]
+pub struct WorkerParams {
+    #[serde(default = "WorkerParams::default_capacity")]
+    capacity: usize,
+    /// Encoding the worker wants requests in, it may reply in either.
+    #[serde(default)]
+    encoding: Encoding,
+    /// Protocol version the worker speaks, workers that don't
+    /// send one predate versioning.
+    #[serde(default)]
+    version: u32,
+    /// Stable name the worker's reputation is kept under across
+    /// reconnects, scoped to the tenant of its API key. Ignored for
+    /// anonymous workers, their reputation isn't kept.
+    identity: Option<String>,
+}
+
+impl WorkerParams {
+    fn default_capacity() -> usize {
+        1
+    }
+}
+
 pub async fn ws_handler(
     Extension(handles): Extension<Handles>,
+    State(state): State<AppState>,
     upgrade: WebSocketUpgrade,
     // TODO: behind a proxy, extract X-Forwarded-For ip
-    RemoteAddress(addr): RemoteAddress
-) -> impl IntoResponse {
+    RemoteAddress(addr): RemoteAddress,
+    MaybeTenant(tenant): MaybeTenant,
+    Query(params): Query<WorkerParams>,
+) -> Response {
+    if !proto::is_compatible(params.version) {
+        tracing::info!(
+            "Rejecting worker [{}] speaking protocol version {}",
+            addr,
+            params.version
+        );
+        let message = format!(
+            "Unsupported protocol version {}, expected {} to {}",
+            params.version,
+            proto::MIN_PROTOCOL_VERSION,
+            proto::PROTOCOL_VERSION
+        );
+        return Status::new(StatusKind::BadRequest, message).into_response();
+    }
+    if handles.registry.is_closed() {
+        let message = "Shutting down, not accepting workers";
+        return Status::new(StatusKind::ServiceUnavailable, message).into_response();
+    }
+    let tier = match &tenant {
+        None => TrustTier::Anonymous,
+        Some(tenant) if tenant.is_admin() => TrustTier::Trusted,
+        Some(tenant) if tenant.has_scope(Scope::Worker) => TrustTier::SemiTrusted,
+        Some(_) => {
+            let message = "API key isn't allowed to connect workers";
+            return Status::new(StatusKind::Forbidden, message).into_response();
+        }
+    };
+    // Anyone could claim an anonymous identity, and authenticated
+    // workers without one resume the default record rather than
+    // starting over.
+    let reputation = match &tenant {
+        Some(tenant) => {
+            let identity = params.identity.as_deref().unwrap_or("default");
+            let identity = format!("{}/{identity}", tenant.name);
+            load_reputation(&state, identity, tier).await
+        }
+        None => Reputation::unidentified(tier),
+    };
     let registry = handles.registry;
-    upgrade.on_upgrade(move |socket| handle(registry, socket, addr))
+    let events = handles.events;
+    upgrade
+        .on_upgrade(move |socket| handle(registry, events, state, socket, addr, params, reputation))
+}
+
+/// Restores the record of a returning worker, starting over
+/// when it can't be loaded.
+async fn load_reputation(state: &AppState, identity: String, tier: TrustTier) -> Reputation {
+    let record = match state.get_db_conn().await {
+        Ok(mut conn) => WorkerReputation::load(identity.clone(), tier, &mut conn)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match record {
+        Ok(record) => Reputation::restore(record, tier),
+        Err(e) => {
+            tracing::warn!("Failed to load reputation of worker {identity}: {e}");
+            Reputation::restore(WorkerReputation::new(identity, tier), tier)
+        }
+    }
+}
+
+async fn save_reputation(state: &AppState, reputation: &Reputation) {
+    let Some(record) = reputation.changes() else {
+        return;
+    };
+    let saved = match state.get_db_conn().await {
+        Ok(mut conn) => record.save(&mut conn).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = saved {
+        tracing::warn!(
+            "Failed to save reputation of worker {}: {e}",
+            record.identity
+        );
+    }
+}
+
+async fn store_log(state: &AppState, dispatch_id: String, level: LogLevel, message: String) {
+    let invocation_id = RequestId::of_dispatch(&dispatch_id);
+    let request_id = RequestId::of_dispatch(invocation_id);
+    let log = NewInvocationLog::new(request_id, invocation_id, &dispatch_id, level, message);
+    let stored = match state.get_db_conn().await {
+        Ok(mut conn) => log.insert(&mut conn).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = stored {
+        tracing::warn!("Failed to store log of request {dispatch_id}: {e}");
+    }
 }
 
-async fn handle(registry: Arc<Registry>, socket: WebSocket, addr: String) {
+async fn handle(
+    registry: Arc<Registry>,
+    events: Arc<EventBus>,
+    state: AppState,
+    socket: WebSocket,
+    addr: String,
+    params: WorkerParams,
+    reputation: Reputation,
+) {
     tracing::debug!("Worker [{}] connected.", addr);
-    let handle = registry.register().await;
+    let handle = match registry.register(params.capacity, reputation).await {
+        Ok(handle) => handle,
+        Err(e) => {
+            tracing::debug!("Worker [{}] turned away: {e}", addr);
+            return;
+        }
+    };
     let node_id = handle.id;
-    let reply_pool = Arc::new(WSReplyPool::new());
+    let reputation = handle.reputation.clone();
+    let worker = RelayedWorker {
+        id: node_id,
+        health: handle.health.clone(),
+        reputation: reputation.clone(),
+        heartbeat_timeout: handle.heartbeat_timeout,
+    };
+    let encoding = params.encoding;
+    let reply_pool = Arc::new(WSReplyPool::new(registry.clone()));
     let (sender, receiver) = socket.split();
     // Task to receive messages from Registry and relay
     // them to the worker.
     let mut worker_relay_task = {
         let reply_pool = reply_pool.clone();
-        tokio::spawn(async { worker_relay(sender, handle, reply_pool).await })
+        tokio::spawn(async move { worker_relay(sender, handle, reply_pool, encoding).await })
     };
     // Task to receive messages from workers and relay
     // them to registry
-    let mut registry_relay_task =
-        tokio::spawn(async { registry_relay(receiver, reply_pool).await });
+    let mut registry_relay_task = {
+        let reply_pool = reply_pool.clone();
+        let state = state.clone();
+        tokio::spawn(
+            async move { registry_relay(receiver, state, events, reply_pool, worker).await },
+        )
+    };
 
     tokio::select! {
         maybe_handle = (&mut worker_relay_task) => {
@@ -99,60 +333,104 @@ async fn handle(registry: Arc<Registry>, socket: WebSocket, addr: String) {
         },
     }
     tracing::debug!("Worker [{}] disconnected.", addr);
+    // Deregister first so failed-over requests are not
+    // scheduled on this worker again.
     registry.deregister(&node_id).await;
+    reply_pool.disconnect().await;
+    save_reputation(&state, &reputation).await;
 }
 
-async fn worker_relay(
-    mut socket: SplitSink<WebSocket, Message>,
+async fn worker_relay<S: Sink<Message> + Unpin>(
+    mut socket: S,
     mut handle: RegistryHandle,
     reply_pool: Arc<WSReplyPool>,
+    encoding: Encoding,
 ) -> RegistryHandle {
-    let mut cycles = 0;
+    let mut heartbeat = tokio::time::interval(handle.heartbeat_interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut draining = false;
     loop {
-        match handle.receiver.try_recv() {
-            Ok(msg) => {
-                let ctrl = send_to_worker(msg, &mut socket, &reply_pool).await;
-                if ctrl.is_break() {
-                    // Worker likely disconnected
-                    break;
+        tokio::select! {
+            maybe_msg = handle.receiver.recv() => {
+                match maybe_msg {
+                    Some(msg) => {
+                        draining |= matches!(msg, WorkerMsg::Drain { .. });
+                        let ctrl = send_to_worker(msg, &mut socket, &reply_pool, encoding).await;
+                        if ctrl.is_break() {
+                            // Worker likely disconnected
+                            break;
+                        }
+                    }
+                    // Registry dropped the worker
+                    None => break,
                 }
             }
-            Err(_) => {}
-        };
-        // TODO: make pinging every 100 cycles configurable.
-        cycles = (cycles + 1) % 100;
-        if cycles == 0 {
-            let ping = socket.send(Message::Ping(vec![0x41, 0x42, 0x43])).await;
-            if ping.is_err() {
-                break;
+            _ = heartbeat.tick() => {
+                reply_pool.sweep().await;
+                if draining && reply_pool.is_empty().await {
+                    tracing::info!("Worker drained, disconnecting..");
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+                let ping = socket.send(Message::Ping(handle.health.ping_payload())).await;
+                if ping.is_err() {
+                    break;
+                }
             }
         }
-        tokio::time::sleep(Duration::from_millis(1)).await;
     }
     handle
 }
 
-async fn send_to_worker(
+async fn send_to_worker<S: Sink<Message> + Unpin>(
     msg: WorkerMsg,
-    socket: &mut SplitSink<WebSocket, Message>,
+    socket: &mut S,
     reply_pool: &Arc<WSReplyPool>,
+    encoding: Encoding,
 ) -> ControlFlow<(), ()> {
     tracing::trace!("Relaying message {msg:?} to worker");
     match msg {
         WorkerMsg::Invoke {
+            request_id,
             name,
             uri,
             signature,
             args,
+            deadline,
             sender,
+            span,
         } => {
-            let request_id = Uuid::new_v4().to_string();
+            let span = tracing::info_span!(parent: &span, "relay", request_id = %request_id);
             let reply_sender = sender;
-            let ws_msg: WSProto =
-                WSProto::invoke_request(request_id.clone(), name, uri, signature, args);
-            // TODO: insert call timestamp to cleanup senders
-            reply_pool.register(request_id, reply_sender).await;
-            let sent_status = socket.send(Message::Text(ws_msg.to_json())).await;
+            let traceparent = telemetry::traceparent(&span);
+            let ws_msg: WSProto = WSProto::invoke_request(
+                request_id.clone(),
+                name,
+                uri,
+                signature,
+                args,
+                traceparent,
+            );
+            reply_pool
+                .register(request_id, reply_sender, deadline)
+                .await;
+            let sent_status = socket.send(ws_msg.encode(encoding)).instrument(span).await;
+            if sent_status.is_err() {
+                return ControlFlow::Break(());
+            }
+        }
+        WorkerMsg::Cancel { request_id } => {
+            reply_pool.cancel(&request_id).await;
+            let ws_msg = WSProto::cancel_request(request_id);
+            let sent_status = socket.send(ws_msg.encode(encoding)).await;
+            if sent_status.is_err() {
+                return ControlFlow::Break(());
+            }
+        }
+        // Worker asked for it, nothing to tell
+        WorkerMsg::Drain { notify: false } => {}
+        WorkerMsg::Drain { notify: true } => {
+            let sent_status = socket.send(WSProto::Drain.encode(encoding)).await;
             if sent_status.is_err() {
                 return ControlFlow::Break(());
             }
@@ -161,7 +439,26 @@ async fn send_to_worker(
     ControlFlow::Continue(())
 }
 
-async fn registry_relay(mut socket: SplitStream<WebSocket>, reply_pool: Arc<WSReplyPool>) {
+/// Worker whose messages the registry relay handles.
+struct RelayedWorker {
+    id: Uuid,
+    health: Arc<WorkerHealth>,
+    reputation: Arc<Reputation>,
+    heartbeat_timeout: Duration,
+}
+
+async fn registry_relay(
+    mut socket: SplitStream<WebSocket>,
+    state: AppState,
+    events: Arc<EventBus>,
+    reply_pool: Arc<WSReplyPool>,
+    worker: RelayedWorker,
+) {
+    let RelayedWorker {
+        health,
+        heartbeat_timeout,
+        ..
+    } = &worker;
     tracing::trace!("Starting registry relay for worker");
     loop {
         tokio::select! {
@@ -169,7 +466,8 @@ async fn registry_relay(mut socket: SplitStream<WebSocket>, reply_pool: Arc<WSRe
                 match maybe_msg {
                     // TODO: Ugly
                     Some(Ok(msg)) => {
-                        let ctrl = process_worker_msg(msg, &reply_pool).await;
+                        health.seen();
+                        let ctrl = process_worker_msg(msg, &state, &events, &reply_pool, &worker).await;
                         if ctrl.is_break() {
                             break;
                         }
@@ -179,69 +477,262 @@ async fn registry_relay(mut socket: SplitStream<WebSocket>, reply_pool: Arc<WSRe
                         break;
 
                     }
-                    // TODO: properly handle empty response
-                    None => continue,
+                    // Stream ended, worker is gone
+                    None => break,
                 }
             }
-            _ = tokio::time::sleep(Duration::from_secs(60)) => {
-                tracing::warn!("Worker hasn't sent a message in 60 seconds, disconnecting..");
+            _ = tokio::time::sleep(*heartbeat_timeout) => {
+                tracing::warn!(
+                    "Worker hasn't sent a message in {}s, disconnecting..",
+                    heartbeat_timeout.as_secs()
+                );
                 break
             }
         }
     }
 }
 
-async fn process_worker_msg(msg: Message, reply_pool: &Arc<WSReplyPool>) -> ControlFlow<(), ()> {
-    match msg {
+async fn process_worker_msg(
+    msg: Message,
+    state: &AppState,
+    events: &EventBus,
+    reply_pool: &Arc<WSReplyPool>,
+    worker: &RelayedWorker,
+) -> ControlFlow<(), ()> {
+    let RelayedWorker {
+        id: worker_id,
+        health,
+        reputation,
+        ..
+    } = worker;
+    let decoded = match msg {
         Message::Text(response_body) => {
             tracing::trace!("Worker responded: {response_body:?}");
-            let response = match WSProto::from_json(&response_body) {
-                Ok(payload) => payload,
-                // WS Client sending malformed payloads
-                // Assume malicious and disconnect
-                Err(_) => {
-                    tracing::info!(
-                        "Disconnecting, Worker responded with malformed response {}",
-                        response_body
-                    );
+            WSProto::from_json(&response_body).map_err(Into::into)
+        }
+        Message::Binary(response_body) => {
+            tracing::trace!("Worker responded with {} bytes", response_body.len());
+            WSProto::from_msgpack(&response_body)
+        }
+        Message::Pong(payload) => {
+            health.pong(&payload);
+            tracing::trace!("Worker pong, round-trip: {:?}", health.rtt());
+            return ControlFlow::Continue(());
+        }
+        Message::Close(_) => {
+            tracing::debug!("Worker terminated connection");
+            return ControlFlow::Break(());
+        }
+        unhandled => {
+            tracing::warn!("Unexpected message from worker: {:?}", unhandled);
+            return ControlFlow::Continue(());
+        }
+    };
+    let response = match decoded {
+        Ok(payload) => payload,
+        // WS Client sending malformed payloads
+        // Assume malicious and disconnect
+        Err(e) => {
+            tracing::info!("Disconnecting, Worker responded with malformed response ({e})");
+            reputation.record(Outcome::Malformed);
+            return ControlFlow::Break(());
+        }
+    };
+    match response {
+        WSProto::Result { ref request_id, .. } | WSProto::Error { ref request_id, .. } => {
+            let request_id = request_id.clone();
+            let msg: RegistryMsg = response.into();
+            let reply_status = reply_pool.reply(&request_id, msg).await;
+            match reply_status {
+                Err(ReplyError::Late) => {
+                    tracing::debug!("Worker replied to {request_id} after its deadline");
+                }
+                // Likely malicious behavior?
+                // Worker sending return values unprovoked?
+                // disconnect
+                Err(ReplyError::Unknown) => {
+                    reputation.record(Outcome::Malformed);
                     return ControlFlow::Break(());
                 }
-            };
-
-            match response {
-                WSProto::Result {
-                    request_id,
-                    content,
-                } => {
-                    let msg: RegistryMsg = RegistryMsg::InvokeResult(content);
-                    let reply_status = reply_pool.reply(&request_id, msg).await;
-                    match reply_status {
-                        // Likely malicious behavior?
-                        // Worker sending return values unprovoked?
-                        // disconnect
-                        Err(_) => return ControlFlow::Break(()),
-                        _ => (),
-                    }
+                _ => (),
+            }
+        }
+        WSProto::Log {
+            request_id,
+            level,
+            message,
+        } => {
+            // Workers may only log for requests they're executing,
+            // and only so much
+            let (level, message) = match reply_pool.admit_log(&request_id).await {
+                LogAdmission::Accepted => (level, message),
+                LogAdmission::Truncated => {
+                    let message = format!("Log truncated after {MAX_LOG_LINES} lines");
+                    (LogLevel::Warn, message)
                 }
-                msg => {
-                    // WS client sending illegal messages i.e. WSProto::Invoke
-                    // disconnect
-                    tracing::info!("Disconnecting, Worker sent an illegal message {:?}", msg);
-                    return ControlFlow::Break(());
+                LogAdmission::Dropped => return ControlFlow::Continue(()),
+                LogAdmission::Unknown => {
+                    tracing::debug!("Dropping log of unknown request {request_id}");
+                    return ControlFlow::Continue(());
                 }
+            };
+            events.log(&request_id, level, &message);
+            let state = state.clone();
+            tokio::spawn(async move { store_log(&state, request_id, level, message).await });
+        }
+        WSProto::Drain => {
+            if let Err(e) = reply_pool.registry.drain(worker_id, false).await {
+                tracing::warn!("Failed to drain worker {worker_id}: {e}");
             }
         }
+        msg => {
+            // WS client sending illegal messages i.e. WSProto::Invoke
+            // disconnect
+            tracing::info!("Disconnecting, Worker sent an illegal message {:?}", msg);
+            return ControlFlow::Break(());
+        }
+    }
+    ControlFlow::Continue(())
+}
 
-        Message::Pong(_) => {
-            // TODO: Should update worker last_ts
+#[cfg(test)]
+mod bench {
+    //! Idle CPU and dispatch latency of the relays, run with
+    //! `cargo test --release bench -- --ignored --nocapture`.
+    use super::*;
+    use crate::{
+        db::models::{FunctionType, InvokePolicy},
+        settings::{RegistrySettings, SchedulerKind},
+    };
+    use futures::channel::mpsc;
+    use serde_json::json;
+
+    const WORKERS: usize = 1000;
+    const IDLE_WINDOW: Duration = Duration::from_secs(10);
+    const DISPATCHES: usize = 5000;
+
+    fn settings() -> RegistrySettings {
+        RegistrySettings {
+            channel_size: 16,
+            timeout_secs: 5,
+            scheduler: SchedulerKind::RoundRobin,
+            max_retries: 0,
+            queue_timeout_ms: 1000,
+            heartbeat_interval_secs: 5,
+            heartbeat_timeout_secs: 60,
+            drain_timeout_secs: 1,
+            min_score: 0.0,
+            min_ready_workers: 0,
         }
+    }
 
-        Message::Close(_) => {
-            tracing::debug!("Worker terminated connection");
-            return ControlFlow::Break(());
+    /// CPU time used by the process so far.
+    fn cpu_time() -> Duration {
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+        // Fields after the parenthesized command name, starting at state
+        let fields: Vec<&str> = stat
+            .rsplit_once(')')
+            .unwrap()
+            .1
+            .split_whitespace()
+            .collect();
+        let ticks: u64 = fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap();
+        // utime and stime are in USER_HZ, 100 on Linux
+        Duration::from_millis(ticks * 10)
+    }
+
+    /// Connects a worker whose socket is an in-memory channel,
+    /// it echoes the first argument of every invocation.
+    async fn connect(registry: &Arc<Registry>) {
+        let handle = registry
+            .register(1, Reputation::unidentified(TrustTier::Trusted))
+            .await
+            .unwrap();
+        let health = handle.health.clone();
+        let reply_pool = Arc::new(WSReplyPool::new(registry.clone()));
+        let (socket, mut frames) = mpsc::unbounded();
+        tokio::spawn(worker_relay(
+            socket,
+            handle,
+            reply_pool.clone(),
+            Encoding::Json,
+        ));
+        tokio::spawn(async move {
+            while let Some(frame) = frames.next().await {
+                health.seen();
+                let Message::Text(body) = frame else {
+                    continue;
+                };
+                if let Ok(WSProto::Invoke {
+                    request_id, args, ..
+                }) = WSProto::from_json(&body)
+                {
+                    let reply = WSProto::Result {
+                        request_id: request_id.clone(),
+                        content: args[0].clone(),
+                    };
+                    let _ = reply_pool.reply(&request_id, reply.into()).await;
+                }
+            }
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn relay_idle_cpu_and_dispatch_latency() {
+        let registry = Arc::new(Registry::start(&settings()));
+        for _ in 0..WORKERS {
+            connect(&registry).await;
         }
+        // Let the relays send their first heartbeat
+        tokio::time::sleep(Duration::from_secs(1)).await;
 
-        unhandled => tracing::warn!("Unexpected message from worker: {:?}", unhandled),
-    };
-    ControlFlow::Continue(())
+        let before = cpu_time();
+        tokio::time::sleep(IDLE_WINDOW).await;
+        let idle = cpu_time() - before;
+        let per_worker = idle.as_secs_f64() / IDLE_WINDOW.as_secs_f64() / WORKERS as f64;
+        println!(
+            "idle: {WORKERS} workers used {idle:?} of CPU in {IDLE_WINDOW:?}, {:.3}µs/s per worker",
+            per_worker * 1e6
+        );
+
+        let signature: FunctionType = serde_json::from_value(json!({
+            "params": [{"type": "i32"}],
+            "shim_idx": 0,
+            "ret": {"type": "i32"},
+            "inner_ret": null,
+        }))
+        .unwrap();
+        let policy = InvokePolicy {
+            idempotent: false,
+            replicas: 1,
+            quorum: 1,
+            min_tier: TrustTier::Anonymous,
+        };
+        let mut latencies = Vec::with_capacity(DISPATCHES);
+        for i in 0..DISPATCHES {
+            let args = [json!(i)];
+            let started = Instant::now();
+            let invocation = registry
+                .invoke(
+                    &RequestId::generate(),
+                    "echo",
+                    "echo",
+                    &signature,
+                    &args,
+                    policy,
+                )
+                .await;
+            latencies.push(started.elapsed());
+            assert_eq!(invocation.result.unwrap(), args[0]);
+        }
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!(
+            "dispatch: {DISPATCHES} invocations, p50 {:?}, p99 {:?}, max {:?}",
+            percentile(50),
+            percentile(99),
+            percentile(100)
+        );
+    }
 }
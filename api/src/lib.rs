@@ -4,9 +4,11 @@ pub mod extract;
 pub mod handlers;
 pub mod proto;
 mod registry;
+pub mod request_id;
 mod settings;
 mod state;
 mod status;
+pub mod telemetry;
 mod util;
 
 pub use extensions::Handles;
@@ -1,11 +1,20 @@
 use axum::{
+    body::Body,
     extract::Extension,
+    http::Request,
+    middleware,
     routing::{get, post},
     Router,
 };
 use faas::{
-    handlers::{DeployHandler, InvokeHandler, WSHandler},
-    AppState, Handles, Settings,
+    handlers::{
+        CreateKeyHandler, DeployHandler, DrainWorkerHandler, HealthHandler, InvocationLogsHandler,
+        InvokeHandler, ListFunctionsHandler, ListWorkersHandler, MetricsHandler,
+        ProtocolSchemaHandler, ReadinessHandler, StreamEventsHandler, ThrottleMiddleware,
+        WSHandler,
+    },
+    request_id::{self, RequestId},
+    telemetry, AppState, Handles, Settings,
 };
 
 use diesel_async::{
@@ -13,40 +22,105 @@ use diesel_async::{
     AsyncPgConnection,
 };
 use std::{error::Error, net::SocketAddr};
+use tokio::signal::unix::{signal, SignalKind};
 use tower::ServiceBuilder;
 use tower_http::{
     cors::{Any, CorsLayer},
     services::ServeDir,
     trace::TraceLayer,
 };
+use tracing::Span;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let settings = Settings::new()?;
+    telemetry::init(&settings.telemetry)?;
     tracing::info!("Running with settings: {:?}", settings);
-    tracing_subscriber::fmt::init();
     let handles = Handles::new(&settings)?;
+    let registry = handles.registry.clone();
+    let events = handles.events.clone();
     let extra_layers = ServiceBuilder::new()
-        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(request_id::middleware))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
         // allow requests from any origin
         .layer(CorsLayer::new().allow_origin(Any))
         .layer(Extension(handles));
 
     let db_config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(settings.db_url);
     let db_pool = Pool::builder().build(db_config).await.unwrap();
-    let state = AppState::new(db_pool);
-    let app = Router::new()
-        .nest_service("/assets", ServeDir::new(&settings.http.assets_directory))
-        .route("/functions", post(DeployHandler))
+    let state = AppState::new(db_pool, &settings.auth);
+    let api = Router::new()
+        .route("/functions", post(DeployHandler).get(ListFunctionsHandler))
         .route("/functions/:id", post(InvokeHandler))
+        .route("/functions/:id/logs/stream", get(StreamEventsHandler))
+        .route("/invocations/:id/logs", get(InvocationLogsHandler))
+        .route("/api_keys", post(CreateKeyHandler))
+        .route("/workers", get(ListWorkersHandler))
+        .route("/workers/:id/drain", post(DrainWorkerHandler))
         .route("/ws", get(WSHandler))
+        .route_layer(middleware::from_fn(ThrottleMiddleware));
+    let app = Router::new()
+        .nest_service("/assets", ServeDir::new(&settings.http.assets_directory))
+        .merge(api)
+        .route("/protocol/schema", get(ProtocolSchemaHandler))
+        .route("/metrics", get(MetricsHandler))
+        .route("/healthz", get(HealthHandler))
+        .route("/readyz", get(ReadinessHandler))
         .layer(extra_layers)
         .with_state(state);
 
     let listen_addr: SocketAddr = "0.0.0.0:8090".parse()?;
     tracing::info!("Server started: Listening on: {}", listen_addr);
+    let shutdown = {
+        let registry = registry.clone();
+        async move {
+            shutdown_signal().await;
+            registry.close();
+            // Event streams never end by themselves
+            events.close();
+        }
+    };
+    // Stops accepting connections on shutdown and waits for in-flight
+    // requests, upgraded worker connections aren't waited for.
     axum::Server::bind(&listen_addr)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
         .await?;
+    registry.shutdown().await;
+    telemetry::shutdown();
     Ok(())
 }
+
+/// Root span of a request, continuing the caller's trace if any.
+fn request_span(request: &Request<Body>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = tracing::field::Empty,
+    );
+    if let Some(id) = request.extensions().get::<RequestId>() {
+        span.record("request_id", id.as_str());
+    }
+    telemetry::continue_trace(&span, request.headers());
+    span
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+    let terminate = async {
+        signal(SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutdown signal received");
+}
@@ -1,10 +1,35 @@
-use crate::db::models::FunctionType;
+use crate::db::models::{FunctionType, LogLevel};
+use rmpv::Value as MsgValue;
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsValue;
-use tokio::sync::oneshot::Sender;
+use axum::extract::ws::Message;
+use thiserror::Error;
+use tokio::{sync::oneshot::Sender, time::Instant};
+use tracing::Span;
+/// Version of the WS protocol, bumped on breaking changes to `WSProto`.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version workers may still connect with.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+pub fn is_compatible(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// JSON Schema of every message in the protocol, tagged with
+/// the protocol version.
+pub fn schema() -> RootSchema {
+    let mut schema = schema_for!(WSProto);
+    schema
+        .schema
+        .extensions
+        .insert("version".to_owned(), PROTOCOL_VERSION.into());
+    schema
+}
+
 /// WSProto: the protocol between
 /// the axum WS handler and WS clients.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WSProto {
     Invoke {
@@ -14,11 +39,84 @@ pub enum WSProto {
         uri: String,
         signature: FunctionType,
         args: Vec<JsValue>,
+        /// W3C trace context workers can continue the trace from.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        traceparent: Option<String>,
     },
     Result {
         request_id: String,
+        /// Return value as JSON, 64-bit integers past 2^53 are sent
+        /// as decimal strings. Left out, i.e. `null`, for unit.
+        #[serde(default)]
         content: JsValue,
     },
+    /// Registry gave up on the request, cooperative workers
+    /// should stop executing it.
+    Cancel {
+        request_id: String,
+    },
+    /// Sent by the registry when it stops scheduling to the worker,
+    /// or by a worker asking to be taken out of rotation. The
+    /// connection is closed once in-flight requests are done.
+    Drain,
+    /// Worker failed to execute the request.
+    Error {
+        request_id: String,
+        kind: WorkerErrorKind,
+        message: String,
+        #[serde(default)]
+        trap_info: Option<JsValue>,
+    },
+    /// Line the function logged, printed or panicked with while
+    /// the worker executed the request.
+    Log {
+        request_id: String,
+        level: LogLevel,
+        message: String,
+    },
+}
+
+/// Wire encoding of `WSProto`, negotiated when the worker connects.
+/// JSON travels in text frames, MessagePack in binary frames.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("malformed json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("malformed msgpack: {0}")]
+    Msgpack(#[from] rmpv::decode::Error),
+    #[error("unexpected msgpack message: {0}")]
+    Message(#[from] rmpv::ext::Error),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerErrorKind {
+    /// The function trapped, e.g. panicked or hit `unreachable`.
+    Trap,
+    /// Arguments couldn't be passed to the function.
+    BadArguments,
+    /// Worker couldn't download or instantiate the module.
+    FetchFailed,
+    /// Function signature isn't supported by the worker.
+    Unsupported,
+    Internal,
+}
+
+/// Execution error reported by a worker.
+#[derive(Serialize, Debug, Error)]
+#[error("{message}")]
+pub struct WorkerError {
+    pub kind: WorkerErrorKind,
+    pub message: String,
+    pub trap_info: Option<JsValue>,
 }
 
 impl WSProto {
@@ -28,6 +126,7 @@ impl WSProto {
         uri: String,
         signature: FunctionType,
         args: Vec<JsValue>,
+        traceparent: Option<String>,
     ) -> WSProto {
         Self::Invoke {
             request_id,
@@ -35,8 +134,13 @@ impl WSProto {
             uri,
             signature,
             args,
+            traceparent,
         }
     }
+    pub fn cancel_request(request_id: String) -> WSProto {
+        Self::Cancel { request_id }
+    }
+
     pub fn from_json(body: &str) -> Result<Self, serde_json::Error> {
         // TODO: should return result
         serde_json::from_str(body)
@@ -46,12 +150,114 @@ impl WSProto {
         // Shouldn't fail..
         serde_json::to_string(self).unwrap()
     }
+
+    /// Encodes the message as MessagePack, `Vec<u8>` and `&[u8]`
+    /// arguments are sent as msgpack binaries instead of arrays.
+    pub fn to_msgpack(&self) -> Vec<u8> {
+        // Shouldn't fail..
+        let mut value = Self::to_msg_value(&serde_json::to_value(self).unwrap());
+        if let Self::Invoke { signature, .. } = self {
+            let args = match &mut value {
+                MsgValue::Map(fields) => fields
+                    .iter_mut()
+                    .find(|(k, _)| k.as_str() == Some("args"))
+                    .map(|(_, v)| v),
+                _ => None,
+            };
+            if let Some(MsgValue::Array(args)) = args {
+                for (arg, ty) in args.iter_mut().zip(signature.params()) {
+                    if ty.is_bytes() {
+                        *arg = Self::to_binary(arg);
+                    }
+                }
+            }
+        }
+        let mut buf = vec![];
+        rmpv::encode::write_value(&mut buf, &value).unwrap();
+        buf
+    }
+
+    /// Decodes a MessagePack message, binaries sent by the worker
+    /// are turned into byte arrays.
+    pub fn from_msgpack(mut body: &[u8]) -> Result<Self, DecodeError> {
+        let mut value = rmpv::decode::read_value(&mut body)?;
+        Self::from_binaries(&mut value);
+        Ok(rmpv::ext::from_value(value)?)
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Message {
+        match encoding {
+            Encoding::Json => Message::Text(self.to_json()),
+            Encoding::Msgpack => Message::Binary(self.to_msgpack()),
+        }
+    }
+
+    /// Converts a JSON value keeping its shape, structs and enums
+    /// stay maps keyed by field name as they are in JSON.
+    fn to_msg_value(value: &JsValue) -> MsgValue {
+        match value {
+            JsValue::Null => MsgValue::Nil,
+            JsValue::Bool(b) => MsgValue::Boolean(*b),
+            JsValue::Number(n) => n
+                .as_u64()
+                .map(MsgValue::from)
+                .or_else(|| n.as_i64().map(MsgValue::from))
+                .unwrap_or_else(|| MsgValue::F64(n.as_f64().unwrap_or_default())),
+            JsValue::String(s) => MsgValue::from(s.as_str()),
+            JsValue::Array(items) => {
+                MsgValue::Array(items.iter().map(Self::to_msg_value).collect())
+            }
+            JsValue::Object(fields) => MsgValue::Map(
+                fields
+                    .iter()
+                    .map(|(k, v)| (MsgValue::from(k.as_str()), Self::to_msg_value(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn to_binary(arg: &MsgValue) -> MsgValue {
+        let bytes: Option<Vec<u8>> = match arg {
+            MsgValue::Array(items) => items
+                .iter()
+                .map(|i| i.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect(),
+            _ => None,
+        };
+        // Leave anything that isn't a byte array to the worker
+        // to reject.
+        bytes.map_or_else(|| arg.clone(), MsgValue::Binary)
+    }
+
+    fn from_binaries(value: &mut MsgValue) {
+        match value {
+            MsgValue::Binary(bytes) => {
+                let items = bytes.iter().map(|b| MsgValue::from(*b)).collect();
+                *value = MsgValue::Array(items);
+            }
+            MsgValue::Array(items) => items.iter_mut().for_each(Self::from_binaries),
+            MsgValue::Map(fields) => fields
+                .iter_mut()
+                .for_each(|(_, v)| Self::from_binaries(v)),
+            _ => (),
+        }
+    }
 }
 
 impl Into<RegistryMsg> for WSProto {
     fn into(self) -> RegistryMsg {
         match self {
             Self::Result { content, .. } => RegistryMsg::InvokeResult(content),
+            Self::Error {
+                kind,
+                message,
+                trap_info,
+                ..
+            } => RegistryMsg::InvokeError(WorkerError {
+                kind,
+                message,
+                trap_info,
+            }),
             _ => panic!("Cannot cast {:?} to RegistryMsg", self),
         }
     }
@@ -60,16 +266,115 @@ impl Into<RegistryMsg> for WSProto {
 #[derive(Debug)]
 pub enum RegistryMsg {
     InvokeResult(JsValue),
+    InvokeError(WorkerError),
     Disconnected,
+    /// Worker didn't reply before the request's deadline.
+    Expired,
 }
 
 #[derive(Debug)]
 pub enum WorkerMsg {
     Invoke {
+        request_id: String,
         name: String,
         uri: String,
         signature: FunctionType,
         args: Vec<JsValue>,
+        deadline: Instant,
         sender: Sender<RegistryMsg>,
+        /// Dispatching span, the relay's span is its child.
+        span: Span,
     },
+    /// Registry stopped waiting for `request_id`.
+    Cancel { request_id: String },
+    /// Worker was taken out of rotation, it's only told when it
+    /// didn't ask for it itself.
+    Drain { notify: bool },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn signature() -> FunctionType {
+        serde_json::from_value(json!({
+            "params": [
+                {"type": "vector", "content": {"type": "u8"}},
+                {"type": "ref", "content": {"type": "slice", "content": {"type": "u8"}}},
+                {"type": "i32"},
+            ],
+            "shim_idx": 0,
+            "ret": {"type": "vector", "content": {"type": "u8"}},
+            "inner_ret": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn msgpack_round_trip_sends_byte_arrays_as_binaries() {
+        let args = vec![json!([0, 1, 255]), json!([]), json!(7)];
+        let msg = WSProto::invoke_request(
+            "req.1".into(),
+            "echo".into(),
+            "assets/echo.wasm".into(),
+            signature(),
+            args.clone(),
+            None,
+        );
+        let encoded = msg.to_msgpack();
+
+        let raw = rmpv::decode::read_value(&mut encoded.as_slice()).unwrap();
+        let raw_args = raw
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k.as_str() == Some("args"));
+        let raw_args = raw_args.unwrap().1.as_array().unwrap();
+        assert_eq!(raw_args[0], MsgValue::Binary(vec![0, 1, 255]));
+        assert_eq!(raw_args[1], MsgValue::Binary(vec![]));
+        assert_eq!(raw_args[2], MsgValue::from(7));
+
+        match WSProto::from_msgpack(&encoded).unwrap() {
+            WSProto::Invoke {
+                request_id,
+                name,
+                args: decoded,
+                ..
+            } => {
+                assert_eq!(request_id, "req.1");
+                assert_eq!(name, "echo");
+                assert_eq!(decoded, args);
+            }
+            other => panic!("decoded {other:?}"),
+        }
+    }
+
+    #[test]
+    fn msgpack_binary_results_decode_to_byte_arrays() {
+        let reply = MsgValue::Map(vec![
+            ("type".into(), "result".into()),
+            ("request_id".into(), "req.1".into()),
+            ("content".into(), MsgValue::Binary(vec![4, 2])),
+        ]);
+        let mut encoded = vec![];
+        rmpv::encode::write_value(&mut encoded, &reply).unwrap();
+        match WSProto::from_msgpack(&encoded).unwrap() {
+            WSProto::Result {
+                request_id,
+                content,
+            } => {
+                assert_eq!(request_id, "req.1");
+                assert_eq!(content, json!([4, 2]));
+            }
+            other => panic!("decoded {other:?}"),
+        }
+    }
+    #[test]
+    fn unit_results_may_leave_content_out() {
+        match WSProto::from_json(r#"{"type": "result", "request_id": "req.1"}"#).unwrap() {
+            WSProto::Result { content, .. } => assert_eq!(content, JsValue::Null),
+            other => panic!("decoded {other:?}"),
+        }
+    }
 }
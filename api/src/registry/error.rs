@@ -1,3 +1,4 @@
+use crate::proto::WorkerError;
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendTimeoutError;
 
@@ -11,10 +12,52 @@ pub enum BackendError {
     NoReply,
     #[error("encountered internal error while invoking function")]
     InternalNodeHandling,
+    #[error("worker disconnected while invoking function")]
+    WorkerLost,
+    #[error("all workers are at capacity, try again later")]
+    Overloaded,
+    #[error("worker replied after the deadline")]
+    DeadlineExceeded,
+    #[error("worker returned a value that doesn't match the function's return type")]
+    InvalidResult,
+    #[error("not enough workers connected to dispatch to {0} of them")]
+    NotEnoughWorkers(usize),
+    #[error("workers didn't agree on the result")]
+    NoQuorum,
+    #[error("no such worker")]
+    UnknownWorker,
+    #[error("registry is shutting down")]
+    ShuttingDown,
+    #[error("function failed: {0}")]
+    Execution(WorkerError),
+}
+
+impl BackendError {
+    /// Name of the variant, used to label metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NoWorkersAvailable => "no_workers_available",
+            Self::Timeout => "timeout",
+            Self::NoReply => "no_reply",
+            Self::InternalNodeHandling => "internal_node_handling",
+            Self::WorkerLost => "worker_lost",
+            Self::Overloaded => "overloaded",
+            Self::DeadlineExceeded => "deadline_exceeded",
+            Self::InvalidResult => "invalid_result",
+            Self::NotEnoughWorkers(_) => "not_enough_workers",
+            Self::NoQuorum => "no_quorum",
+            Self::UnknownWorker => "unknown_worker",
+            Self::ShuttingDown => "shutting_down",
+            Self::Execution(_) => "execution",
+        }
+    }
 }
 
 impl<T> From<SendTimeoutError<T>> for BackendError {
-    fn from(_: SendTimeoutError<T>) -> BackendError {
-        BackendError::Timeout
+    fn from(error: SendTimeoutError<T>) -> BackendError {
+        match error {
+            SendTimeoutError::Timeout(_) => BackendError::Timeout,
+            SendTimeoutError::Closed(_) => BackendError::WorkerLost,
+        }
     }
 }
@@ -1,5 +1,10 @@
 mod error;
+mod quorum;
 mod registry;
+mod reputation;
+mod scheduler;
 mod worker;
 pub use error::BackendError;
-pub use registry::{Registry, RegistryHandle};
+pub use registry::{Registry, RegistryHandle, WorkerInfo, REPLY_GRACE};
+pub use reputation::{Outcome, Reputation};
+pub use worker::WorkerHealth;
@@ -0,0 +1,140 @@
+use super::error::BackendError;
+use serde_json::Value as JsValue;
+
+/// Replies of a replicated invocation, collected until `quorum`
+/// of them agree or agreement can no longer be reached.
+pub struct Tally<W> {
+    quorum: usize,
+    pending: usize,
+    results: Vec<(W, JsValue)>,
+    errors: Vec<BackendError>,
+}
+
+pub enum Verdict<W> {
+    /// `agreeing` returned `value`, `outvoted` returned something else
+    Agreed {
+        value: JsValue,
+        agreeing: Vec<W>,
+        outvoted: Vec<W>,
+    },
+    /// No quorum, `results` workers returned a value
+    Split { results: usize },
+    /// Every worker failed, with the first error
+    Failed(BackendError),
+}
+
+impl<W> Tally<W> {
+    pub fn new(replicas: usize, quorum: usize) -> Self {
+        Self {
+            quorum,
+            pending: replicas,
+            results: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Records a worker's reply, `value` must be normalized already.
+    /// Returns the verdict as soon as it can't change anymore.
+    pub fn record(
+        &mut self,
+        worker: W,
+        reply: Result<JsValue, BackendError>,
+    ) -> Option<Verdict<W>> {
+        self.pending = self.pending.saturating_sub(1);
+        match reply {
+            Ok(value) => self.results.push((worker, value)),
+            Err(e) => self.errors.push(e),
+        }
+        let agreeing = |value: &JsValue| self.results.iter().filter(|(_, v)| v == value).count();
+        if let Some(i) = self
+            .results
+            .iter()
+            .position(|(_, v)| agreeing(v) >= self.quorum)
+        {
+            let value = self.results[i].1.clone();
+            let (agreeing, outvoted) = self
+                .results
+                .drain(..)
+                .partition::<Vec<_>, _>(|(_, v)| *v == value);
+            return Some(Verdict::Agreed {
+                value,
+                agreeing: agreeing.into_iter().map(|(w, _)| w).collect(),
+                outvoted: outvoted.into_iter().map(|(w, _)| w).collect(),
+            });
+        }
+        let best = self
+            .results
+            .iter()
+            .map(|(_, v)| agreeing(v))
+            .max()
+            .unwrap_or(0);
+        if best + self.pending >= self.quorum {
+            return None;
+        }
+        Some(
+            match (self.results.is_empty(), self.errors.drain(..).next()) {
+                (true, Some(e)) => Verdict::Failed(e),
+                _ => Verdict::Split {
+                    results: self.results.len(),
+                },
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn agrees_once_quorum_is_reached() {
+        let mut tally = Tally::new(3, 2);
+        assert!(tally.record(1, Ok(json!(4))).is_none());
+        assert!(tally.record(2, Ok(json!(5))).is_none());
+        match tally.record(3, Ok(json!(4))) {
+            Some(Verdict::Agreed {
+                value,
+                agreeing,
+                outvoted,
+            }) => {
+                assert_eq!(value, json!(4));
+                assert_eq!(agreeing, vec![1, 3]);
+                assert_eq!(outvoted, vec![2]);
+            }
+            _ => panic!("expected an agreement"),
+        }
+    }
+
+    #[test]
+    fn stops_waiting_when_quorum_is_out_of_reach() {
+        let mut tally = Tally::new(3, 3);
+        assert!(tally.record(1, Ok(json!(4))).is_none());
+        // The pending reply can't make up for the disagreement
+        assert!(matches!(
+            tally.record(2, Ok(json!(5))),
+            Some(Verdict::Split { results: 2 })
+        ));
+    }
+
+    #[test]
+    fn errors_count_against_the_quorum() {
+        let mut tally = Tally::new(3, 2);
+        assert!(tally.record(1, Err(BackendError::Timeout)).is_none());
+        assert!(tally.record(2, Ok(json!(4))).is_none());
+        assert!(matches!(
+            tally.record(3, Err(BackendError::WorkerLost)),
+            Some(Verdict::Split { results: 1 })
+        ));
+    }
+
+    #[test]
+    fn reports_the_first_error_when_every_worker_failed() {
+        let mut tally = Tally::<u32>::new(2, 1);
+        assert!(tally.record(1, Err(BackendError::Timeout)).is_none());
+        assert!(matches!(
+            tally.record(2, Err(BackendError::WorkerLost)),
+            Some(Verdict::Failed(BackendError::Timeout))
+        ));
+    }
+}
@@ -1,44 +1,138 @@
-use super::worker::{WorkerHandle, WorkersBucket};
+use super::quorum::{Tally, Verdict};
+use super::reputation::{Outcome, Reputation};
+use super::scheduler;
+use super::worker::{WorkerHandle, WorkerHealth, WorkersBucket};
 use super::error::{BackendError};
 
 use crate::{
-    db::models::FunctionType,
+    db::models::{FunctionType, InvokePolicy, TrustTier},
     proto::{WorkerMsg, RegistryMsg},
+    request_id::RequestId,
     settings::RegistrySettings,
-    status::Status,
 };
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
 use serde_json::Value as JsValue;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 use tokio::{
     sync::{
-        mpsc::{channel, Receiver},
+        mpsc::{channel, Receiver, Sender},
         oneshot,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tracing::Span;
 use uuid::Uuid;
 
+/// How long ids of cancelled requests are remembered, so late
+/// replies aren't mistaken for unprovoked ones.
+const CANCELLED_TTL: Duration = Duration::from_secs(300);
+/// Callers keep waiting this long past a request's deadline, so
+/// replies arriving meanwhile are reported as late rather than the
+/// request timing out.
+pub const REPLY_GRACE: Duration = Duration::from_secs(2);
+
 pub struct Registry {
     workers: WorkersBucket,
     channel_size: usize,
     timeout: Duration,
+    max_retries: u32,
+    queue_timeout: Duration,
+    heartbeat_interval: Duration,
+    heartbeat_timeout: Duration,
+    drain_timeout: Duration,
+    min_ready_workers: usize,
+    /// Set once shutdown begins, new workers are turned away.
+    closed: AtomicBool,
+    /// Cancelled request ids, mapped to when they can be forgotten.
+    cancelled: Mutex<HashMap<String, Instant>>,
 }
 
 impl Registry {
     pub fn start(settings: &RegistrySettings) -> Self {
+        let heartbeat_timeout = Duration::from_secs(settings.heartbeat_timeout_secs);
         Self {
-            workers: WorkersBucket::new(),
+            workers: WorkersBucket::new(
+                scheduler::init(&settings.scheduler),
+                heartbeat_timeout,
+                settings.min_score,
+            ),
             channel_size: settings.channel_size,
             timeout: Duration::from_secs(settings.timeout_secs as u64),
+            max_retries: settings.max_retries,
+            queue_timeout: Duration::from_millis(settings.queue_timeout_ms),
+            heartbeat_interval: Duration::from_secs(settings.heartbeat_interval_secs),
+            heartbeat_timeout,
+            drain_timeout: Duration::from_secs(settings.drain_timeout_secs),
+            min_ready_workers: settings.min_ready_workers,
+            closed: AtomicBool::new(false),
+            cancelled: Mutex::new(HashMap::new()),
         }
     }
 
-    pub async fn register(&self) -> RegistryHandle {
+    /// Records `request_id` as cancelled, the caller stopped waiting.
+    pub fn cancel(&self, request_id: &str) {
+        let now = Instant::now();
+        let mut cancelled = self.cancelled.lock().unwrap();
+        cancelled.retain(|_, forget_at| *forget_at > now);
+        cancelled.insert(request_id.to_owned(), now + CANCELLED_TTL);
+    }
+
+    pub fn is_cancelled(&self, request_id: &str) -> bool {
+        self.cancelled.lock().unwrap().contains_key(request_id)
+    }
+
+    /// Stops accepting workers, connected ones keep being scheduled.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub async fn register(
+        &self,
+        capacity: usize,
+        reputation: Reputation,
+    ) -> Result<RegistryHandle, BackendError> {
         let worker_id = Uuid::new_v4();
-        tracing::trace!("Registering new worker: {worker_id}");
+        tracing::trace!(
+            "Registering new worker: {worker_id} (capacity: {capacity}, tier: {:?}, score: {:.2})",
+            reputation.tier,
+            reputation.score()
+        );
         let (sender, receiver) = channel(self.channel_size);
-        let worker = WorkerHandle::new(worker_id, sender);
+        let health = Arc::new(WorkerHealth::new());
+        let reputation = Arc::new(reputation);
+        let worker = WorkerHandle::new(
+            worker_id,
+            sender,
+            capacity,
+            health.clone(),
+            reputation.clone(),
+        );
         self.workers.add(worker).await;
-        RegistryHandle::new(worker_id, receiver)
+        // Checked once added, so shutdown either sees the worker
+        // or the worker sees shutdown.
+        if self.is_closed() {
+            let _ = self.workers.remove(&worker_id).await;
+            return Err(BackendError::ShuttingDown);
+        }
+        Ok(RegistryHandle {
+            id: worker_id,
+            receiver,
+            health,
+            reputation,
+            heartbeat_interval: self.heartbeat_interval,
+            heartbeat_timeout: self.heartbeat_timeout,
+        })
     }
 
     pub async fn deregister(&self, worker_id: &Uuid) {
@@ -46,79 +140,403 @@ impl Registry {
         let maybe_handle = self.workers.remove(worker_id).await;
         match maybe_handle {
             Ok(_) => {}
+            // Evicted for missing heartbeats
+            Err(BackendError::UnknownWorker) => {
+                tracing::trace!("Worker already evicted: {worker_id}")
+            }
             Err(e) => tracing::warn!("Error while deregistering worker: {worker_id}, Error: {e}"),
         }
     }
 
+    /// Stops scheduling requests to a worker and disconnects it once
+    /// its in-flight requests are done. The worker is told unless it
+    /// asked to be drained, i.e. `notify` isn't set.
+    pub async fn drain(&self, worker_id: &Uuid, notify: bool) -> Result<(), BackendError> {
+        let worker = self.workers.drain(worker_id).await?;
+        tracing::info!(
+            "Draining worker: {worker_id}, in-flight: {}",
+            worker.in_flight()
+        );
+        worker
+            .sender
+            .send_timeout(WorkerMsg::Drain { notify }, self.timeout)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn workers(&self) -> Vec<WorkerInfo> {
+        self.workers
+            .list()
+            .await
+            .iter()
+            .map(|w| WorkerInfo {
+                id: w.id,
+                capacity: w.capacity,
+                in_flight: w.in_flight(),
+                draining: w.is_draining(),
+                identity: w.reputation.identity.clone(),
+                tier: w.reputation.tier,
+                score: w.reputation.score(),
+                disagreements: w.reputation.disagreements(),
+                latency_ms: w.reputation.latency_ms(),
+                rtt_ms: w.health.rtt().map(|rtt| rtt.as_secs_f64() * 1000.0),
+            })
+            .collect()
+    }
+
+    /// Workers requests may be scheduled to, along with how many
+    /// must be for the API to be ready.
+    pub async fn readiness(&self) -> (usize, usize) {
+        let available = self
+            .workers
+            .schedulable(&[], TrustTier::Anonymous)
+            .await
+            .len();
+        (available, self.min_ready_workers)
+    }
+
+    /// Drains every worker and waits, up to the drain timeout,
+    /// for in-flight requests to finish, then disconnects them.
+    pub async fn shutdown(&self) {
+        self.close();
+        let workers = self.workers.drain_all().await;
+        tracing::info!("Draining {} workers before shutdown", workers.len());
+        for worker in workers {
+            let _ = worker
+                .sender
+                .send_timeout(WorkerMsg::Drain { notify: true }, self.drain_timeout)
+                .await;
+        }
+        if self.workers.wait_idle(self.drain_timeout).await.is_err() {
+            tracing::warn!(
+                "Workers still busy after {}s, shutting down anyway",
+                self.drain_timeout.as_secs()
+            );
+        }
+        // Relays end once the registry side of their channel is gone
+        for worker in self.workers.list().await {
+            let _ = self.workers.remove(&worker.id).await;
+        }
+    }
+
+    /// Invokes a function on behalf of the HTTP request `request_id`,
+    /// idempotent functions are retried on other workers when the
+    /// worker is lost mid-call or returns an invalid result.
+    /// Replicated functions are dispatched to several workers instead.
+    #[tracing::instrument(
+        name = "registry.invoke",
+        skip_all,
+        fields(request_id = %request_id, function = name)
+    )]
     pub async fn invoke(
         &self,
-        name: String,
-        uri: String,
-        signature: FunctionType,
-        args: Vec<JsValue>,
-    ) -> Result<Status, BackendError> {
-        let worker = self.workers.get_worker().await?;
-        let worker_id = worker.lock().await.id;
-        tracing::trace!("Invoking function ({uri}) on worker ({worker_id})");
-
-        let (sender, mut receiver) = oneshot::channel();
-        let msg = WorkerMsg::Invoke {
-            name,
-            uri,
-            signature,
-            args,
-            sender,
+        request_id: &RequestId,
+        name: &str,
+        uri: &str,
+        signature: &FunctionType,
+        args: &[JsValue],
+        policy: InvokePolicy,
+    ) -> Invocation {
+        if policy.replicas > 1 {
+            return self
+                .invoke_replicated(request_id, name, uri, signature, args, policy)
+                .await;
+        }
+        let max_attempts = if policy.idempotent {
+            self.max_retries + 1
+        } else {
+            1
         };
+        let mut attempts = 0;
+        // Retried on other workers, a lost one may not be
+        // deregistered yet
+        let mut tried = vec![];
+        let mut failure = None;
+        loop {
+            attempts += 1;
+            let result = match self
+                .workers
+                .reserve(uri, self.queue_timeout, &tried, policy.min_tier)
+                .await
+            {
+                Ok((worker, _in_flight)) => {
+                    tried.push(worker.id);
+                    self.dispatch(&worker, request_id, name, uri, signature, args)
+                        .await
+                        .map(|value| (worker, value))
+                }
+                // No other worker left to retry on
+                Err(BackendError::NoWorkersAvailable) if failure.is_some() => {
+                    Err(failure.take().unwrap_or(BackendError::NoWorkersAvailable))
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e @ (BackendError::WorkerLost | BackendError::InvalidResult))
+                    if attempts < max_attempts =>
+                {
+                    tracing::info!(
+                        "Worker failed while invoking ({uri}): {e}, retrying ({attempts}/{max_attempts})"
+                    );
+                    failure = Some(e);
+                }
+                Ok((worker, value)) => {
+                    return Invocation {
+                        attempts,
+                        result: Ok(value),
+                        workers: vec![worker.reputation.clone()],
+                    }
+                }
+                Err(e) => {
+                    return Invocation {
+                        attempts,
+                        result: Err(e),
+                        workers: vec![],
+                    }
+                }
+            }
+        }
+    }
 
-        worker.lock()
+    /// Dispatches the call to `policy.replicas` distinct workers and
+    /// replies once `policy.quorum` of them returned the same result.
+    /// Workers outvoted by the quorum have the disagreement recorded.
+    async fn invoke_replicated(
+        &self,
+        request_id: &RequestId,
+        name: &str,
+        uri: &str,
+        signature: &FunctionType,
+        args: &[JsValue],
+        policy: InvokePolicy,
+    ) -> Invocation {
+        let replicas = policy.replicas as usize;
+        let quorum = policy.quorum as usize;
+        let reserved = match self
+            .workers
+            .reserve_many(uri, replicas, self.queue_timeout, policy.min_tier)
             .await
-            .sender
-            .send_timeout(msg, self.timeout)
-            .await?;
-        let mut rcv_return_task = tokio::spawn(async move {
-            loop {
-                let msg = receiver.try_recv();
-                match msg {
-                    Ok(m) => return Ok(m),
-                    _ => tokio::time::sleep(Duration::from_millis(5)).await,
-                }
+        {
+            Ok(reserved) => reserved,
+            Err(e) => {
+                let e = match e {
+                    BackendError::NoWorkersAvailable => BackendError::NotEnoughWorkers(replicas),
+                    e => e,
+                };
+                return Invocation {
+                    attempts: 0,
+                    result: Err(e),
+                    workers: vec![],
+                };
             }
+        };
+        tracing::trace!("Invoking function ({uri}) on {replicas} workers, quorum: {quorum}");
 
-            // NOTE: Unreachable, but needed for type inference.
-            Err(BackendError::Timeout)
-        });
-
-        let result_v = tokio::select! {
-            // XXX REPLACE ME
-            ret_val = (&mut rcv_return_task) => {
-                match ret_val {
-                    Ok(r) => r,
-                    Err(_) => {
-                        tracing::warn!("Worker: {:?} malformed reply", worker_id);
-                        return Err(BackendError::NoReply)
-                    }
+        let mut replies: FuturesUnordered<_> = reserved
+            .iter()
+            .map(|(worker, _)| async move {
+                let reply = self
+                    .dispatch(worker, request_id, name, uri, signature, args)
+                    .await;
+                (worker, reply)
+            })
+            .collect();
+        // Normalized, runtimes may encode the same result differently
+        let ret = signature.ret();
+        let mut tally = Tally::new(replicas, quorum);
+        let mut verdict = None;
+        while let Some((worker, reply)) = replies.next().await {
+            verdict = tally.record(worker, reply.map(|value| ret.normalize(&value)));
+            if verdict.is_some() {
+                break;
+            }
+        }
+        let (result, workers) = match verdict {
+            Some(Verdict::Agreed {
+                value,
+                agreeing,
+                outvoted,
+            }) => {
+                for worker in outvoted {
+                    tracing::warn!(
+                        "Worker: {:?} disagreed with the quorum while invoking ({uri})",
+                        worker.id
+                    );
+                    worker.reputation.record(Outcome::Disagreement);
+                }
+                let workers = agreeing.iter().map(|w| w.reputation.clone()).collect();
+                (Ok(value), workers)
+            }
+            Some(Verdict::Failed(e)) => (Err(e), vec![]),
+            Some(Verdict::Split { results }) => {
+                // Can't tell which side is right without a quorum
+                if results > 1 {
+                    tracing::warn!(
+                        "Workers disagreed while invoking ({uri}), no {quorum} of {results} results match"
+                    );
                 }
-            },
-            _ = tokio::time::sleep(self.timeout) => {
-                rcv_return_task.abort();
-                return Err(BackendError::Timeout)
+                (Err(BackendError::NoQuorum), vec![])
+            }
+            // Every reply settles the tally, only reached without replicas
+            None => (Err(BackendError::NoQuorum), vec![]),
+        };
+        Invocation {
+            attempts: replicas as u32,
+            result,
+            workers,
+        }
+    }
+
+    /// Sends the call to a worker holding a reserved slot and
+    /// waits for its reply.
+    #[tracing::instrument(skip_all, fields(worker = %worker.id))]
+    async fn dispatch(
+        &self,
+        worker: &Arc<WorkerHandle>,
+        request_id: &RequestId,
+        name: &str,
+        uri: &str,
+        signature: &FunctionType,
+        args: &[JsValue],
+    ) -> Result<JsValue, BackendError> {
+        let worker_id = worker.id;
+        tracing::trace!(
+            "Invoking function ({uri}) on worker ({worker_id}), in-flight: {}",
+            worker.in_flight()
+        );
+
+        let request_id = request_id.dispatch_id();
+        let ret = signature.ret();
+        let deadline = Instant::now() + self.timeout;
+        let (sender, receiver) = oneshot::channel();
+        let msg = WorkerMsg::Invoke {
+            request_id: request_id.clone(),
+            name: name.to_owned(),
+            uri: uri.to_owned(),
+            signature: signature.clone(),
+            args: args.to_vec(),
+            deadline,
+            sender,
+            span: Span::current(),
+        };
+
+        worker.sender.send_timeout(msg, self.timeout).await?;
+        let sent_at = Instant::now();
+        // Fires on timeout, or when the caller drops this future
+        // because the HTTP client went away.
+        let mut cancel_guard = CancelGuard {
+            registry: self,
+            sender: &worker.sender,
+            request_id: Some(request_id),
+        };
+        let result_v = match tokio::time::timeout_at(deadline + REPLY_GRACE, receiver).await {
+            Ok(Ok(msg)) => msg,
+            // Reply pool dropped without replying, worker is gone
+            Ok(Err(_)) => return Err(BackendError::WorkerLost),
+            Err(_) => {
+                worker.reputation.record(Outcome::Timeout);
+                return Err(BackendError::Timeout);
             }
-        }?;
+        };
+        cancel_guard.disarm();
 
         match result_v {
-            RegistryMsg::InvokeResult(r) => Ok(Status::ok_payload(r)),
-            _ => panic!("Unexpected RegistryMsg received {:?}", result_v)
+            RegistryMsg::InvokeResult(r) if ret.accepts(&r) => {
+                worker
+                    .reputation
+                    .record(Outcome::Success(sent_at.elapsed()));
+                Ok(r)
+            }
+            RegistryMsg::InvokeResult(r) => {
+                // Buggy or malicious, either way it can't be trusted
+                // with other requests. Its relay disconnects once the
+                // requests already sent to it are done.
+                tracing::warn!(
+                    "Worker: {:?} returned {} for a function returning {:?}, disconnecting",
+                    worker_id,
+                    r,
+                    ret
+                );
+                worker.reputation.record(Outcome::Malformed);
+                let _ = self.workers.remove(&worker_id).await;
+                Err(BackendError::InvalidResult)
+            }
+            RegistryMsg::Disconnected => {
+                tracing::warn!("Worker: {:?} disconnected mid-call", worker_id);
+                Err(BackendError::WorkerLost)
+            }
+            RegistryMsg::InvokeError(e) => {
+                tracing::debug!(
+                    "Worker: {:?} failed to execute ({:?}): {}",
+                    worker_id,
+                    e.kind,
+                    e
+                );
+                Err(BackendError::Execution(e))
+            }
+            RegistryMsg::Expired => {
+                worker.reputation.record(Outcome::Timeout);
+                Err(BackendError::DeadlineExceeded)
+            }
         }
     }
 }
 
-pub struct RegistryHandle {
-    pub id: Uuid,
-    pub receiver: Receiver<WorkerMsg>,
+/// Cancels a dispatched request on drop unless disarmed.
+struct CancelGuard<'a> {
+    registry: &'a Registry,
+    sender: &'a Sender<WorkerMsg>,
+    request_id: Option<String>,
+}
+
+impl CancelGuard<'_> {
+    fn disarm(&mut self) {
+        self.request_id = None;
+    }
 }
 
-impl RegistryHandle {
-    pub fn new(id: Uuid, receiver: Receiver<WorkerMsg>) -> Self {
-        Self { id, receiver }
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(request_id) = self.request_id.take() {
+            tracing::debug!("Cancelling request {request_id}");
+            self.registry.cancel(&request_id);
+            // Relay sweeps it anyway if the channel is full
+            let _ = self.sender.try_send(WorkerMsg::Cancel { request_id });
+        }
     }
 }
+
+#[derive(Serialize)]
+pub struct WorkerInfo {
+    pub id: Uuid,
+    pub capacity: usize,
+    pub in_flight: usize,
+    pub draining: bool,
+    pub identity: Option<String>,
+    pub tier: TrustTier,
+    pub score: f64,
+    /// Times the worker was outvoted by a quorum or failed a spot check.
+    pub disagreements: u64,
+    /// Moving average of reply latency.
+    pub latency_ms: f64,
+    pub rtt_ms: Option<f64>,
+}
+
+/// Outcome of an invocation along with the number of
+/// workers it was dispatched to.
+pub struct Invocation {
+    pub attempts: u32,
+    pub result: Result<JsValue, BackendError>,
+    /// Reputations of the workers that returned the result.
+    pub workers: Vec<Arc<Reputation>>,
+}
+
+pub struct RegistryHandle {
+    pub id: Uuid,
+    pub receiver: Receiver<WorkerMsg>,
+    pub health: Arc<WorkerHealth>,
+    pub reputation: Arc<Reputation>,
+    pub heartbeat_interval: Duration,
+    /// Silence after which the worker is disconnected.
+    pub heartbeat_timeout: Duration,
+}
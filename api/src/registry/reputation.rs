@@ -0,0 +1,147 @@
+use crate::db::models::{TrustTier, WorkerReputation};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+use tokio::time::Duration;
+
+/// Average latency at which a worker's score is halved.
+const LATENCY_SCALE_MS: f64 = 10_000.0;
+/// Weight of the newest sample in the latency moving average.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// What a worker did with a request, as far as trusting it goes.
+pub enum Outcome {
+    /// Replied with a valid result after the given time.
+    Success(Duration),
+    Timeout,
+    /// Sent a reply that couldn't be parsed, wasn't asked for or
+    /// didn't match the function's return type.
+    Malformed,
+    /// Was outvoted by a quorum of other workers, or its result
+    /// didn't match a local re-execution.
+    Disagreement,
+}
+
+/// Track record of a connected worker, shared by the registry
+/// and the worker's relays.
+pub struct Reputation {
+    /// Set when the worker identified itself, the record is
+    /// persisted under it across reconnects.
+    pub identity: Option<String>,
+    pub tier: TrustTier,
+    successes: AtomicU64,
+    timeouts: AtomicU64,
+    malformed: AtomicU64,
+    disagreements: AtomicU64,
+    /// Bits of the `f64` latency moving average, in millis.
+    latency_ms: AtomicU64,
+    /// Counters as restored, only what was recorded since is
+    /// persisted, other workers may share the identity.
+    restored: WorkerReputation,
+}
+
+impl Reputation {
+    /// Reputation of a worker that didn't identify itself, it
+    /// starts from scratch and is never persisted.
+    pub fn unidentified(tier: TrustTier) -> Self {
+        let mut instance = Self::restore(WorkerReputation::new(String::new(), tier), tier);
+        instance.identity = None;
+        instance
+    }
+
+    pub fn restore(record: WorkerReputation, tier: TrustTier) -> Self {
+        let count = |n: i64| AtomicU64::new(n.max(0) as u64);
+        Self {
+            identity: Some(record.identity.clone()),
+            tier,
+            successes: count(record.successes),
+            timeouts: count(record.timeouts),
+            malformed: count(record.malformed),
+            disagreements: count(record.disagreements),
+            latency_ms: AtomicU64::new(record.latency_ms.to_bits()),
+            restored: record,
+        }
+    }
+
+    pub fn record(&self, outcome: Outcome) {
+        let counter = match outcome {
+            Outcome::Success(latency) => {
+                let sample = latency.as_secs_f64() * 1000.0;
+                let average = match self.latency_ms() {
+                    avg if avg > 0.0 => avg + LATENCY_SMOOTHING * (sample - avg),
+                    _ => sample,
+                };
+                self.latency_ms.store(average.to_bits(), Ordering::Relaxed);
+                &self.successes
+            }
+            Outcome::Timeout => &self.timeouts,
+            Outcome::Malformed => &self.malformed,
+            Outcome::Disagreement => &self.disagreements,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn disagreements(&self) -> u64 {
+        self.disagreements.load(Ordering::Relaxed)
+    }
+
+    pub fn latency_ms(&self) -> f64 {
+        f64::from_bits(self.latency_ms.load(Ordering::Relaxed))
+    }
+
+    /// Between 0 and 1, workers without a record start at 0.5.
+    /// Faults weigh more the less likely they are to be accidental
+    /// and slow workers score lower.
+    pub fn score(&self) -> f64 {
+        let load = |n: &AtomicU64| n.load(Ordering::Relaxed) as f64;
+        let successes = load(&self.successes);
+        let penalties =
+            load(&self.timeouts) + 2.0 * load(&self.malformed) + 3.0 * load(&self.disagreements);
+        let reliability = (successes + 1.0) / (successes + penalties + 2.0);
+        reliability / (1.0 + self.latency_ms() / LATENCY_SCALE_MS)
+    }
+
+    /// Counters recorded since the record was restored, to add to
+    /// the persisted ones, if the worker identified itself.
+    pub fn changes(&self) -> Option<WorkerReputation> {
+        let since = |n: &AtomicU64, restored: i64| {
+            (n.load(Ordering::Relaxed) as i64 - restored.max(0)).max(0)
+        };
+        let identity = self.identity.clone()?;
+        let restored = &self.restored;
+        Some(WorkerReputation {
+            identity,
+            updated_at: SystemTime::now(),
+            tier: self.tier.as_str().to_owned(),
+            successes: since(&self.successes, restored.successes),
+            timeouts: since(&self.timeouts, restored.timeouts),
+            malformed: since(&self.malformed, restored.malformed),
+            disagreements: since(&self.disagreements, restored.disagreements),
+            latency_ms: self.latency_ms(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changes_since_restoring_are_persisted() {
+        let mut record = WorkerReputation::new("tenant/default".into(), TrustTier::SemiTrusted);
+        record.successes = 5;
+        record.malformed = 2;
+        let reputation = Reputation::restore(record, TrustTier::SemiTrusted);
+        reputation.record(Outcome::Success(Duration::from_millis(10)));
+        reputation.record(Outcome::Malformed);
+
+        let changes = reputation.changes().unwrap();
+        assert_eq!(changes.successes, 1);
+        assert_eq!(changes.malformed, 1);
+        assert_eq!(changes.timeouts, 0);
+        assert!(Reputation::unidentified(TrustTier::Anonymous)
+            .changes()
+            .is_none());
+    }
+}
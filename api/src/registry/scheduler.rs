@@ -0,0 +1,198 @@
+use super::worker::WorkerHandle;
+use crate::settings::SchedulerKind;
+use rand::Rng;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Picks the worker an invocation is dispatched to,
+/// `workers` is never empty. `key` identifies the invoked
+/// function's module.
+pub trait Scheduler: Send + Sync {
+    fn pick(&self, workers: &[Arc<WorkerHandle>], key: &str) -> usize;
+}
+
+pub fn init(kind: &SchedulerKind) -> Box<dyn Scheduler> {
+    match kind {
+        SchedulerKind::RoundRobin => Box::new(RoundRobin::default()),
+        SchedulerKind::LeastOutstanding => Box::new(LeastOutstanding),
+        SchedulerKind::PowerOfTwoChoices => Box::new(PowerOfTwoChoices),
+        SchedulerKind::WeightedCapacity => Box::new(WeightedCapacity),
+        SchedulerKind::CacheAffinity => Box::new(CacheAffinity),
+    }
+}
+
+#[derive(Default)]
+pub struct RoundRobin {
+    counter: AtomicUsize,
+}
+
+impl Scheduler for RoundRobin {
+    fn pick(&self, workers: &[Arc<WorkerHandle>], _key: &str) -> usize {
+        self.counter.fetch_add(1, Ordering::Relaxed) % workers.len()
+    }
+}
+
+/// Worker with the fewest in-flight requests.
+pub struct LeastOutstanding;
+
+impl Scheduler for LeastOutstanding {
+    fn pick(&self, workers: &[Arc<WorkerHandle>], _key: &str) -> usize {
+        workers
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, w)| w.in_flight())
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+}
+
+/// Samples two workers at random and picks the less loaded one,
+/// avoids herding on a single idle worker under bursts.
+pub struct PowerOfTwoChoices;
+
+impl Scheduler for PowerOfTwoChoices {
+    fn pick(&self, workers: &[Arc<WorkerHandle>], _key: &str) -> usize {
+        let mut rng = rand::thread_rng();
+        let first = rng.gen_range(0..workers.len());
+        let second = rng.gen_range(0..workers.len());
+        if workers[second].in_flight() < workers[first].in_flight() {
+            second
+        } else {
+            first
+        }
+    }
+}
+
+/// Worker with the lowest in-flight to advertised capacity ratio.
+pub struct WeightedCapacity;
+
+impl Scheduler for WeightedCapacity {
+    fn pick(&self, workers: &[Arc<WorkerHandle>], _key: &str) -> usize {
+        workers
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.load().total_cmp(&b.load()))
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+}
+
+/// Routes every function to the same worker so it can reuse the
+/// module it already downloaded, using rendezvous hashing over
+/// worker ids so only a fraction of functions move when workers
+/// join or leave. Falls back to the next preferred worker when
+/// the preferred one is at capacity, and to the least loaded one
+/// when every worker is.
+pub struct CacheAffinity;
+
+impl CacheAffinity {
+    fn score(worker: &WorkerHandle, key: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        worker.id.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl Scheduler for CacheAffinity {
+    fn pick(&self, workers: &[Arc<WorkerHandle>], key: &str) -> usize {
+        let mut ranked: Vec<(u64, usize)> = workers
+            .iter()
+            .enumerate()
+            .map(|(idx, w)| (Self::score(w, key), idx))
+            .collect();
+        ranked.sort_unstable_by(|a, b| b.cmp(a));
+        ranked
+            .iter()
+            .map(|(_, idx)| *idx)
+            .find(|idx| workers[*idx].in_flight() < workers[*idx].capacity)
+            .unwrap_or_else(|| WeightedCapacity.pick(workers, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::models::TrustTier,
+        registry::{reputation::Reputation, worker::WorkerHealth},
+    };
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    fn workers(capacities: &[usize]) -> Vec<Arc<WorkerHandle>> {
+        capacities
+            .iter()
+            .map(|capacity| {
+                let (sender, _) = mpsc::channel(1);
+                Arc::new(WorkerHandle::new(
+                    Uuid::new_v4(),
+                    sender,
+                    *capacity,
+                    Arc::new(WorkerHealth::new()),
+                    Arc::new(Reputation::unidentified(TrustTier::Trusted)),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn least_outstanding_picks_the_fewest_in_flight() {
+        let workers = workers(&[4, 4, 4]);
+        let _busy = [
+            workers[0].try_track(),
+            workers[0].try_track(),
+            workers[2].try_track(),
+        ];
+        assert_eq!(LeastOutstanding.pick(&workers, "func"), 1);
+    }
+
+    #[test]
+    fn weighted_capacity_accounts_for_capacity() {
+        let workers = workers(&[2, 8]);
+        // 1 of 2 slots against 2 of 8 slots
+        let _busy = [
+            workers[0].try_track(),
+            workers[1].try_track(),
+            workers[1].try_track(),
+        ];
+        assert_eq!(LeastOutstanding.pick(&workers, "func"), 0);
+        assert_eq!(WeightedCapacity.pick(&workers, "func"), 1);
+    }
+
+    #[test]
+    fn cache_affinity_only_moves_keys_of_the_leaving_worker() {
+        let mut workers = workers(&[4, 4, 4, 4]);
+        let keys: Vec<String> = (0..200).map(|i| format!("func-{i}")).collect();
+        let picked =
+            |workers: &[Arc<WorkerHandle>], key: &str| workers[CacheAffinity.pick(workers, key)].id;
+        let before: Vec<Uuid> = keys.iter().map(|key| picked(&workers, key)).collect();
+        let left = workers.remove(1).id;
+
+        for (key, id) in keys.iter().zip(before) {
+            let now = picked(&workers, key);
+            if id == left {
+                assert_ne!(now, left);
+            } else {
+                assert_eq!(now, id, "{key} moved to another worker");
+            }
+        }
+    }
+
+    #[test]
+    fn cache_affinity_falls_back_when_preferred_worker_is_full() {
+        let workers = workers(&[1, 1, 1]);
+        let preferred = CacheAffinity.pick(&workers, "func");
+        let _busy = workers[preferred].try_track();
+        let fallback = CacheAffinity.pick(&workers, "func");
+        assert_ne!(fallback, preferred);
+        // The fallback is stable too while the preferred worker is full
+        assert_eq!(CacheAffinity.pick(&workers, "func"), fallback);
+    }
+}
@@ -1,71 +1,635 @@
-use super::error::BackendError;
-use crate::proto::WorkerMsg;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{mpsc::Sender, Mutex, RwLock};
+use super::{error::BackendError, reputation::Reputation, scheduler::Scheduler};
+use crate::{db::models::TrustTier, proto::WorkerMsg};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    sync::{mpsc::Sender, Notify, RwLock},
+    time::{Duration, Instant},
+};
 use uuid::Uuid;
 
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Liveness of a worker, updated by its WS relays from
+/// any message or pong it sends.
+pub struct WorkerHealth {
+    started: Instant,
+    /// Micros since `started` the worker was last heard from.
+    last_seen: AtomicU64,
+    /// Last measured ping round-trip in micros, 0 until measured.
+    rtt: AtomicU64,
+}
+
+impl WorkerHealth {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_seen: AtomicU64::new(0),
+            rtt: AtomicU64::new(0),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+
+    pub fn seen(&self) {
+        self.last_seen.store(self.now(), Ordering::Relaxed);
+    }
+
+    pub fn since_seen(&self) -> Duration {
+        let last_seen = self.last_seen.load(Ordering::Relaxed);
+        Duration::from_micros(self.now().saturating_sub(last_seen))
+    }
+
+    pub fn is_alive(&self, timeout: Duration) -> bool {
+        self.since_seen() < timeout
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.rtt.load(Ordering::Relaxed) {
+            0 => None,
+            rtt => Some(Duration::from_micros(rtt)),
+        }
+    }
+
+    /// Ping payload carrying the send time, echoed back in the pong.
+    pub fn ping_payload(&self) -> Vec<u8> {
+        self.now().to_be_bytes().to_vec()
+    }
+
+    pub fn pong(&self, payload: &[u8]) {
+        self.seen();
+        if let Ok(sent) = <[u8; 8]>::try_from(payload) {
+            let rtt = self.now().saturating_sub(u64::from_be_bytes(sent));
+            self.rtt.store(rtt.max(1), Ordering::Relaxed);
+        }
+    }
+}
+
 pub struct WorkerHandle {
     pub id: Uuid,
     pub sender: Arc<Sender<WorkerMsg>>,
+    /// Concurrent invocations the worker advertised it can handle.
+    pub capacity: usize,
+    in_flight: AtomicUsize,
+    /// Signalled whenever a slot is released, shared by the bucket.
+    released: Arc<Notify>,
+    pub health: Arc<WorkerHealth>,
+    /// Draining workers finish their in-flight requests but
+    /// aren't scheduled new ones.
+    draining: AtomicBool,
+    pub reputation: Arc<Reputation>,
 }
 
 impl WorkerHandle {
-    pub fn new(id: Uuid, sender: Sender<WorkerMsg>) -> Self {
+    pub fn new(
+        id: Uuid,
+        sender: Sender<WorkerMsg>,
+        capacity: usize,
+        health: Arc<WorkerHealth>,
+        reputation: Arc<Reputation>,
+    ) -> Self {
         Self {
             id,
             sender: Arc::new(sender),
+            capacity: capacity.max(1),
+            in_flight: AtomicUsize::new(0),
+            released: Arc::new(Notify::new()),
+            health,
+            draining: AtomicBool::new(false),
+            reputation,
         }
     }
+
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn load(&self) -> f64 {
+        self.in_flight() as f64 / self.capacity as f64
+    }
+
+    /// Reserves one of the worker's slots until the guard is dropped,
+    /// fails if all slots are taken.
+    pub fn try_track(self: &Arc<Self>) -> Option<InFlightGuard> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |n| {
+                (n < self.capacity).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| InFlightGuard {
+                worker: self.clone(),
+            })
+    }
+}
+
+pub struct InFlightGuard {
+    worker: Arc<WorkerHandle>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.worker.in_flight.fetch_sub(1, Ordering::AcqRel);
+        // Every waiter checks, the slot may not be usable by some
+        // of them, e.g. because of their trust tier
+        self.worker.released.notify_waiters();
+    }
 }
 
 pub struct WorkersBucket {
-    workers: RwLock<Vec<Arc<Mutex<WorkerHandle>>>>,
-    key_to_idx: RwLock<HashMap<Uuid, usize>>,
-    counter: RwLock<usize>,
+    /// Keyed by worker id, ordered so schedulers see the same
+    /// order across calls no matter which workers joined or left.
+    workers: RwLock<BTreeMap<Uuid, Arc<WorkerHandle>>>,
+    scheduler: Box<dyn Scheduler>,
+    released: Arc<Notify>,
+    /// Workers silent for longer are evicted before scheduling.
+    liveness_timeout: Duration,
+    /// Workers scoring lower aren't scheduled.
+    min_score: f64,
 }
 
 impl WorkersBucket {
-    pub fn new() -> Self {
+    pub fn new(scheduler: Box<dyn Scheduler>, liveness_timeout: Duration, min_score: f64) -> Self {
         Self {
-            workers: RwLock::new(vec![]),
-            key_to_idx: RwLock::new(HashMap::new()),
-            counter: RwLock::new(0),
+            workers: RwLock::new(BTreeMap::new()),
+            scheduler,
+            released: Arc::new(Notify::new()),
+            liveness_timeout,
+            min_score,
         }
     }
 
-    pub async fn add(&self, worker: WorkerHandle) {
-        let mut workers = self.workers.write().await;
-        self.key_to_idx
+    pub async fn add(&self, mut worker: WorkerHandle) {
+        worker.released = self.released.clone();
+        self.workers
             .write()
             .await
-            .insert(worker.id.clone(), workers.len());
-        workers.push(Arc::new(Mutex::new(worker)))
+            .insert(worker.id, Arc::new(worker));
     }
 
-    pub async fn remove(&self, worker_id: &Uuid) -> Result<Arc<Mutex<WorkerHandle>>, BackendError> {
-        let mut workers = self.workers.write().await;
-        let idx = self
-            .key_to_idx
+    pub async fn remove(&self, worker_id: &Uuid) -> Result<Arc<WorkerHandle>, BackendError> {
+        self.workers
             .write()
             .await
             .remove(worker_id)
-            .ok_or(BackendError::InternalNodeHandling)?;
-        let worker = workers.remove(idx);
-        Ok(worker)
+            .ok_or(BackendError::UnknownWorker)
     }
 
-    pub async fn get_worker(&self) -> Result<Arc<Mutex<WorkerHandle>>, BackendError> {
-        let workers = self.workers.read().await;
+    /// Reserves a slot on a worker for the function identified by `key`,
+    /// other than the `exclude`d ones and trusted at least as `min_tier`.
+    /// Spills over to other workers when the scheduled one is full and
+    /// waits up to `queue_timeout` for a slot when every worker is.
+    pub async fn reserve(
+        &self,
+        key: &str,
+        queue_timeout: Duration,
+        exclude: &[Uuid],
+        min_tier: TrustTier,
+    ) -> Result<(Arc<WorkerHandle>, InFlightGuard), BackendError> {
+        let deadline = Instant::now() + queue_timeout;
+        loop {
+            // Register interest before checking so a release in
+            // between isn't missed.
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            if let Some(reserved) = self.try_reserve(key, exclude, min_tier).await? {
+                return Ok(reserved);
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                return Err(BackendError::Overloaded);
+            }
+        }
+    }
+
+    async fn try_reserve(
+        &self,
+        key: &str,
+        exclude: &[Uuid],
+        min_tier: TrustTier,
+    ) -> Result<Option<(Arc<WorkerHandle>, InFlightGuard)>, BackendError> {
+        let workers = self.schedulable(exclude, min_tier).await;
         if workers.len() == 0 {
+            return Err(BackendError::NoWorkersAvailable);
+        }
+        let idx = self.scheduler.pick(&workers, key);
+        let preferred = workers.get(idx).ok_or(BackendError::InternalNodeHandling)?;
+        let mut spill: Vec<&Arc<WorkerHandle>> = workers.iter().collect();
+        spill.sort_by(|a, b| a.load().total_cmp(&b.load()));
+        let reserved = std::iter::once(preferred)
+            .chain(spill)
+            .find_map(|w| w.try_track().map(|guard| (w.clone(), guard)));
+        Ok(reserved)
+    }
+
+    /// Reserves a slot on each of `count` distinct workers trusted at
+    /// least as `min_tier`, all or none, so concurrent callers can't
+    /// each hold part of what the other waits for. Waits up to
+    /// `queue_timeout` for enough workers to have a free slot.
+    pub async fn reserve_many(
+        &self,
+        key: &str,
+        count: usize,
+        queue_timeout: Duration,
+        min_tier: TrustTier,
+    ) -> Result<Vec<(Arc<WorkerHandle>, InFlightGuard)>, BackendError> {
+        let deadline = Instant::now() + queue_timeout;
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            if let Some(reserved) = self.try_reserve_many(key, count, min_tier).await? {
+                return Ok(reserved);
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                return Err(BackendError::Overloaded);
+            }
+        }
+    }
+
+    async fn try_reserve_many(
+        &self,
+        key: &str,
+        count: usize,
+        min_tier: TrustTier,
+    ) -> Result<Option<Vec<(Arc<WorkerHandle>, InFlightGuard)>>, BackendError> {
+        let workers = self.schedulable(&[], min_tier).await;
+        if workers.len() < count {
+            return Err(BackendError::NoWorkersAvailable);
+        }
+        // Checked before taking any slot, so waiting callers don't
+        // keep taking and releasing them
+        let mut free: Vec<Arc<WorkerHandle>> = workers
+            .into_iter()
+            .filter(|w| w.in_flight() < w.capacity)
+            .collect();
+        if free.len() < count {
+            return Ok(None);
+        }
+        let idx = self.scheduler.pick(&free, key);
+        if idx >= free.len() {
+            return Err(BackendError::InternalNodeHandling);
+        }
+        let preferred = free.swap_remove(idx);
+        free.sort_by(|a, b| a.load().total_cmp(&b.load()));
+        let mut reserved = Vec::with_capacity(count);
+        for worker in std::iter::once(preferred).chain(free) {
+            if let Some(guard) = worker.try_track() {
+                reserved.push((worker, guard));
+            }
+            if reserved.len() == count {
+                return Ok(Some(reserved));
+            }
+        }
+        // Lost a race for some of the slots, the ones taken are
+        // released on return
+        Ok(None)
+    }
+
+    /// Live workers requests may be scheduled to, other than the
+    /// `exclude`d ones and trusted at least as `min_tier`.
+    pub async fn schedulable(
+        &self,
+        exclude: &[Uuid],
+        min_tier: TrustTier,
+    ) -> Vec<Arc<WorkerHandle>> {
+        self.evict_unhealthy().await;
+        self.workers
+            .read()
+            .await
+            .values()
+            .filter(|w| !w.is_draining() && !exclude.contains(&w.id))
+            .filter(|w| w.reputation.tier >= min_tier && w.reputation.score() >= self.min_score)
+            .cloned()
+            .collect()
+    }
+
+    /// Takes a worker out of rotation.
+    pub async fn drain(&self, worker_id: &Uuid) -> Result<Arc<WorkerHandle>, BackendError> {
+        let workers = self.workers.read().await;
+        let worker = workers.get(worker_id).ok_or(BackendError::UnknownWorker)?;
+        worker.drain();
+        Ok(worker.clone())
+    }
+
+    /// Takes every worker out of rotation.
+    pub async fn drain_all(&self) -> Vec<Arc<WorkerHandle>> {
+        let workers = self.workers.read().await;
+        workers.values().for_each(|w| w.drain());
+        workers.values().cloned().collect()
+    }
+
+    pub async fn list(&self) -> Vec<Arc<WorkerHandle>> {
+        self.workers.read().await.values().cloned().collect()
+    }
+
+    /// Waits until no worker has requests in flight, fails
+    /// if some are still running after `timeout`.
+    pub async fn wait_idle(&self, timeout: Duration) -> Result<(), BackendError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let in_flight: usize = self
+                .workers
+                .read()
+                .await
+                .values()
+                .map(|w| w.in_flight())
+                .sum();
+            if in_flight == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(BackendError::Timeout);
+            }
+            // Polled, slot releases only wake waiting reservations
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Drops workers that missed their heartbeats, their relays end
+    /// once the registry side of the channel is gone.
+    async fn evict_unhealthy(&self) {
+        let unhealthy: Vec<Uuid> = self
+            .workers
+            .read()
+            .await
+            .values()
+            .filter(|w| !w.health.is_alive(self.liveness_timeout))
+            .map(|w| w.id)
+            .collect();
+        for worker_id in unhealthy {
+            tracing::warn!("Evicting unresponsive worker: {worker_id}");
+            let _ = self.remove(&worker_id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{registry::scheduler, settings::SchedulerKind};
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Mutex,
+    };
+    use tokio::sync::mpsc;
+
+    const QUEUE_TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn bucket() -> Arc<WorkersBucket> {
+        let scheduler = scheduler::init(&SchedulerKind::LeastOutstanding);
+        Arc::new(WorkersBucket::new(scheduler, Duration::from_secs(60), 0.0))
+    }
+
+    async fn connect(bucket: &WorkersBucket, capacity: usize) -> Uuid {
+        let (sender, _) = mpsc::channel(1);
+        let id = Uuid::new_v4();
+        let worker = WorkerHandle::new(
+            id,
+            sender,
+            capacity,
+            Arc::new(WorkerHealth::new()),
+            Arc::new(Reputation::unidentified(TrustTier::Trusted)),
+        );
+        bucket.add(worker).await;
+        id
+    }
+
+    async fn reserve(
+        bucket: &WorkersBucket,
+    ) -> Result<(Arc<WorkerHandle>, InFlightGuard), BackendError> {
+        bucket
+            .reserve("func", QUEUE_TIMEOUT, &[], TrustTier::Anonymous)
+            .await
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn churn_never_leaks_slots_or_picks_retired_workers() {
+        let bucket = bucket();
+        for _ in 0..4 {
+            connect(&bucket, 2).await;
+        }
+        // Removed or drained workers, no reservation starting
+        // after they're retired may pick them.
+        let retired = Arc::new(Mutex::new(HashSet::new()));
+        let reserved = Arc::new(Mutex::new(HashMap::new()));
+
+        let churn = {
+            let bucket = bucket.clone();
+            let retired = retired.clone();
+            tokio::spawn(async move {
+                for round in 0..200 {
+                    connect(&bucket, 2).await;
+                    let schedulable: Vec<Uuid> = bucket
+                        .list()
+                        .await
+                        .iter()
+                        .filter(|w| !w.is_draining())
+                        .map(|w| w.id)
+                        .collect();
+                    let victim = schedulable[round % schedulable.len()];
+                    if round % 3 == 0 {
+                        bucket.drain(&victim).await.unwrap();
+                    } else {
+                        bucket.remove(&victim).await.unwrap();
+                    }
+                    retired.lock().unwrap().insert(victim);
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        let callers: Vec<_> = (0..16)
+            .map(|_| {
+                let bucket = bucket.clone();
+                let retired = retired.clone();
+                let reserved = reserved.clone();
+                tokio::spawn(async move {
+                    for _ in 0..200 {
+                        let retired_before = retired.lock().unwrap().clone();
+                        match reserve(&bucket).await {
+                            Ok((worker, guard)) => {
+                                assert!(!retired_before.contains(&worker.id));
+                                assert!(worker.in_flight() <= worker.capacity);
+                                reserved.lock().unwrap().insert(worker.id, worker.clone());
+                                tokio::task::yield_now().await;
+                                drop(guard);
+                            }
+                            Err(BackendError::Overloaded) => {}
+                            Err(e) => panic!("unexpected error: {e}"),
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        churn.await.unwrap();
+        for caller in callers {
+            caller.await.unwrap();
+        }
+        let reserved: Vec<_> = reserved.lock().unwrap().values().cloned().collect();
+        assert!(!reserved.is_empty());
+        for worker in reserved {
+            assert_eq!(worker.in_flight(), 0, "worker {} leaked a slot", worker.id);
+        }
+        assert!(bucket.wait_idle(Duration::ZERO).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn drained_worker_keeps_in_flight_requests_until_released() {
+        let bucket = bucket();
+        let id = connect(&bucket, 2).await;
+        let (worker, guard) = reserve(&bucket).await.unwrap();
+        assert_eq!(worker.id, id);
+
+        bucket.drain(&id).await.unwrap();
+        assert!(matches!(
+            reserve(&bucket).await,
             Err(BackendError::NoWorkersAvailable)
-        } else {
-            let mut counter = self.counter.write().await;
-            let worker = workers
-                .get(*counter)
-                .ok_or(BackendError::InternalNodeHandling)?;
-            // Wrap around
-            *counter = (*counter + 1) % workers.len();
-            Ok(worker.clone())
+        ));
+        assert_eq!(worker.in_flight(), 1);
+        assert!(bucket.wait_idle(Duration::from_millis(10)).await.is_err());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(guard);
+        });
+        assert!(bucket.wait_idle(Duration::from_secs(1)).await.is_ok());
+        assert_eq!(worker.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn removed_worker_releases_slots_of_in_flight_requests() {
+        let bucket = bucket();
+        let id = connect(&bucket, 1).await;
+        let (worker, guard) = reserve(&bucket).await.unwrap();
+
+        bucket.remove(&id).await.unwrap();
+        assert!(bucket.list().await.is_empty());
+        assert!(matches!(
+            reserve(&bucket).await,
+            Err(BackendError::NoWorkersAvailable)
+        ));
+        drop(guard);
+        assert_eq!(worker.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn released_slot_wakes_waiting_reservation() {
+        let bucket = bucket();
+        let id = connect(&bucket, 1).await;
+        let (_, guard) = reserve(&bucket).await.unwrap();
+
+        let waiting = {
+            let bucket = bucket.clone();
+            tokio::spawn(async move {
+                bucket
+                    .reserve("func", Duration::from_secs(1), &[], TrustTier::Anonymous)
+                    .await
+                    .map(|(worker, _)| worker.id)
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(guard);
+        assert_eq!(waiting.await.unwrap().unwrap(), id);
+    }
+    #[tokio::test]
+    async fn released_slot_wakes_waiters_that_can_use_it() {
+        let bucket = bucket();
+        connect(&bucket, 1).await;
+        connect(&bucket, 1).await;
+        let (first, first_guard) = reserve(&bucket).await.unwrap();
+        let (second, _second_guard) = reserve(&bucket).await.unwrap();
+        let (first, second) = (first.id, second.id);
+
+        // Waiting for a slot on each worker, in that order
+        let waiter = |exclude: Uuid| {
+            let bucket = bucket.clone();
+            tokio::spawn(async move {
+                bucket
+                    .reserve(
+                        "func",
+                        Duration::from_secs(1),
+                        &[exclude],
+                        TrustTier::Anonymous,
+                    )
+                    .await
+                    .map(|(worker, _)| worker.id)
+            })
+        };
+        let on_second = waiter(first);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let on_first = waiter(second);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        drop(first_guard);
+        let reserved = tokio::time::timeout(Duration::from_millis(200), on_first).await;
+        assert_eq!(reserved.unwrap().unwrap().unwrap(), first);
+        assert!(!on_second.is_finished());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_replicated_reservations_dont_hold_and_wait() {
+        let bucket = bucket();
+        connect(&bucket, 1).await;
+        connect(&bucket, 1).await;
+
+        let callers: Vec<_> = (0..2)
+            .map(|_| {
+                let bucket = bucket.clone();
+                tokio::spawn(async move {
+                    let reserved = bucket
+                        .reserve_many("func", 2, Duration::from_secs(1), TrustTier::Anonymous)
+                        .await?;
+                    let workers: HashSet<Uuid> = reserved.iter().map(|(w, _)| w.id).collect();
+                    assert_eq!(workers.len(), 2);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, BackendError>(())
+                })
+            })
+            .collect();
+        for caller in callers {
+            caller.await.unwrap().unwrap();
         }
+        assert!(matches!(
+            bucket
+                .reserve_many("func", 3, QUEUE_TIMEOUT, TrustTier::Anonymous)
+                .await,
+            Err(BackendError::NoWorkersAvailable)
+        ));
+    }
+
+    #[tokio::test]
+    async fn schedulable_skips_drained_and_silent_workers() {
+        let scheduler = scheduler::init(&SchedulerKind::LeastOutstanding);
+        let bucket = WorkersBucket::new(scheduler, Duration::from_millis(50), 0.0);
+        let id = connect(&bucket, 1).await;
+        let drained = connect(&bucket, 1).await;
+        bucket.drain(&drained).await.unwrap();
+
+        let schedulable = bucket.schedulable(&[], TrustTier::Anonymous).await;
+        assert_eq!(schedulable.len(), 1);
+        assert_eq!(schedulable[0].id, id);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(bucket
+            .schedulable(&[], TrustTier::Anonymous)
+            .await
+            .is_empty());
+        assert!(bucket.list().await.is_empty());
     }
 }
@@ -0,0 +1,85 @@
+use axum::{
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::fmt;
+use uuid::Uuid;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Caller-supplied ids longer than this are replaced.
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Id of an HTTP request, taken from its `X-Request-Id` header or
+/// generated. Available to handlers as an extension.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Random id for requests that didn't come with one.
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let id = value.to_str().ok()?;
+        let valid =
+            !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+        valid.then(|| Self(id.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Id of a request sent to a worker on behalf of this one. Callers
+    /// may reuse ids, and replicas and retries share them, so it's
+    /// suffixed to be unique.
+    pub fn dispatch_id(&self) -> String {
+        format!("{}.{}", self.0, Uuid::new_v4().simple())
+    }
+
+    /// Id of one invocation made by this request. Dispatch ids
+    /// derived from it map back to it with `of_dispatch`.
+    pub fn invocation_id(&self) -> Self {
+        Self(self.dispatch_id())
+    }
+
+    /// Id of the request a `dispatch_id` was sent on behalf of.
+    pub fn of_dispatch(dispatch_id: &str) -> &str {
+        dispatch_id
+            .rsplit_once('.')
+            .map_or(dispatch_id, |(id, _)| id)
+    }
+
+    /// Id of the request being handled by the current task.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|id| id.clone()).ok()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Assigns the request its id and echoes it in the response headers.
+pub async fn middleware<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+    request.extensions_mut().insert(id.clone());
+    let mut response = CURRENT.scope(id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+    response
+}
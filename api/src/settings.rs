@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
+use std::fmt;
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
@@ -7,6 +8,13 @@ pub struct Settings {
     pub storage: StorageSettings,
     pub compiler: CompilerSettings,
     pub registry: RegistrySettings,
+    pub limits: LimitsSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub spot_check: SpotCheckSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
     pub db_url: String,
 }
 
@@ -37,6 +45,119 @@ pub struct CompilerSettings {
 pub struct RegistrySettings {
     pub channel_size: usize,
     pub timeout_secs: usize,
+    #[serde(default)]
+    pub scheduler: SchedulerKind,
+    /// Extra attempts for idempotent functions whose worker was lost.
+    #[serde(default)]
+    pub max_retries: u32,
+    /// How long invocations wait for a free worker slot.
+    #[serde(default)]
+    pub queue_timeout_ms: u64,
+    #[serde(default = "RegistrySettings::default_heartbeat_interval")]
+    pub heartbeat_interval_secs: u64,
+    /// Workers silent for longer are disconnected and evicted.
+    #[serde(default = "RegistrySettings::default_heartbeat_timeout")]
+    pub heartbeat_timeout_secs: u64,
+    /// How long shutdown waits for in-flight requests.
+    #[serde(default = "RegistrySettings::default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+    /// Workers whose reputation score falls below aren't scheduled.
+    #[serde(default)]
+    pub min_score: f64,
+    /// Workers that must be connected for the API to report ready.
+    #[serde(default)]
+    pub min_ready_workers: usize,
+}
+
+impl RegistrySettings {
+    fn default_heartbeat_interval() -> u64 {
+        5
+    }
+
+    fn default_heartbeat_timeout() -> u64 {
+        60
+    }
+
+    fn default_drain_timeout() -> u64 {
+        30
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulerKind {
+    #[default]
+    RoundRobin,
+    LeastOutstanding,
+    PowerOfTwoChoices,
+    WeightedCapacity,
+    CacheAffinity,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LimitsSettings {
+    /// Tokens added to each client's bucket per second.
+    pub rate_per_sec: f64,
+    /// Bucket capacity, i.e. the largest allowed burst.
+    pub burst: u32,
+    pub daily_invocations: Option<i32>,
+    pub daily_deploys: Option<i32>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct AuthSettings {
+    /// Bootstrap key granted every scope, used to issue tenant keys.
+    pub admin_key: Option<String>,
+}
+
+// Settings are logged on startup, keep the admin key out of them.
+impl fmt::Debug for AuthSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthSettings")
+            .field("admin_key", &self.admin_key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SpotCheckSettings {
+    /// Fraction of remote results re-executed locally, 0 disables it.
+    pub sample_rate: f64,
+    /// Reply with the local result when the worker's doesn't match.
+    pub prefer_local: bool,
+    /// Wasm operators a local execution may run.
+    pub fuel: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for SpotCheckSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 0.0,
+            prefer_local: false,
+            fuel: 100_000_000,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// OTLP gRPC collector spans are exported to, e.g.
+    /// `http://localhost:4317`, spans are only logged without.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "faas".into(),
+        }
+    }
 }
 
 impl Settings {
@@ -45,8 +166,25 @@ impl Settings {
         let mode = std::env::var("FAAS_ENV").unwrap_or_else(|_| "dev".into());
         let config = Config::builder()
             .add_source(File::with_name(&format!("{}/{}.toml", config_base, mode)))
-            .add_source(config::Environment::with_prefix("FAAS"))
+            // nested keys are separated by `__`, e.g. FAAS_AUTH__ADMIN_KEY
+            .add_source(
+                config::Environment::with_prefix("FAAS")
+                    .prefix_separator("_")
+                    .separator("__"),
+            )
             .build()?;
-        return config.try_deserialize();
+        let settings: Self = config.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let rate = self.limits.rate_per_sec;
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(ConfigError::Message(format!(
+                "limits.rate_per_sec must be positive, got {rate}"
+            )));
+        }
+        Ok(())
     }
 }
@@ -1,4 +1,4 @@
-use crate::db::DBPool;
+use crate::{db::DBPool, settings::AuthSettings};
 use axum::extract::FromRef;
 use diesel_async::{
     pooled_connection::bb8::{PooledConnection, RunError},
@@ -7,11 +7,15 @@ use diesel_async::{
 #[derive(FromRef, Clone)]
 pub struct AppState {
     pub pool: DBPool,
+    pub admin_key: Option<String>,
 }
 
 impl AppState {
-    pub fn new(pool: DBPool) -> Self {
-        Self { pool }
+    pub fn new(pool: DBPool, auth: &AuthSettings) -> Self {
+        Self {
+            pool,
+            admin_key: auth.admin_key.clone(),
+        }
     }
 
     pub async fn get_db_conn<'a>(
@@ -1,10 +1,12 @@
+use crate::request_id::RequestId;
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
 use serde_json::Value as JsValue;
+use std::time::Duration;
 
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -13,8 +15,14 @@ pub enum StatusKind {
     BadRequest,
     InternalError,
     NotFound,
+    Unauthorized,
     Forbidden,
     UnsupportedMediaType,
+    TooManyRequests,
+    ServiceUnavailable,
+    UnprocessableEntity,
+    BadGateway,
+    GatewayTimeout,
 }
 
 impl StatusKind {
@@ -24,8 +32,14 @@ impl StatusKind {
             Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             Self::BadRequest => StatusCode::BAD_REQUEST,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
             Self::Forbidden => StatusCode::FORBIDDEN,
             Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::BadGateway => StatusCode::BAD_GATEWAY,
+            Self::GatewayTimeout => StatusCode::GATEWAY_TIMEOUT,
         }
     }
 }
@@ -34,6 +48,10 @@ impl StatusKind {
 pub struct Status {
     kind: StatusKind,
     message: JsValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip)]
+    retry_after: Option<Duration>,
 }
 
 impl Status {
@@ -41,9 +59,17 @@ impl Status {
         Self {
             kind,
             message: serde_json::to_value(message).unwrap(),
+            request_id: RequestId::current().map(|id| id.to_string()),
+            retry_after: None,
         }
     }
 
+    /// Sets the `Retry-After` header, rounded up to whole seconds.
+    pub fn with_retry_after(mut self, after: Duration) -> Self {
+        self.retry_after = Some(after);
+        self
+    }
+
     pub fn ok() -> Self {
         Self::new(StatusKind::Ok, "success".to_owned())
     }
@@ -60,6 +86,31 @@ impl Status {
 impl IntoResponse for Status {
     fn into_response(self) -> Response {
         let status_code: StatusCode = self.kind.as_http();
-        (status_code, Json(self)).into_response()
+        match self.retry_after {
+            Some(after) => {
+                let secs = after.as_secs() + (after.subsec_nanos() > 0) as u64;
+                (status_code, [(RETRY_AFTER, secs.to_string())], Json(self)).into_response()
+            }
+            None => (status_code, Json(self)).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        let response = Status::new(StatusKind::TooManyRequests, "slow down")
+            .with_retry_after(Duration::from_millis(1200))
+            .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+
+        let response = Status::new(StatusKind::TooManyRequests, "slow down")
+            .with_retry_after(Duration::from_secs(3))
+            .into_response();
+        assert_eq!(response.headers()[RETRY_AFTER], "3");
     }
 }
@@ -0,0 +1,73 @@
+use crate::settings::TelemetrySettings;
+use axum::http::HeaderMap;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::{collections::HashMap, error::Error};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// Installs the global subscriber, spans are exported over OTLP
+/// when an endpoint is configured.
+pub fn init(settings: &TelemetrySettings) -> Result<(), Box<dyn Error>> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let otel = match &settings.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint);
+            let resource = Resource::new(vec![KeyValue::new(
+                "service.name",
+                settings.service_name.clone(),
+            )]);
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(trace::config().with_resource(resource))
+                .install_batch(opentelemetry::runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel)
+        .try_init()?;
+    Ok(())
+}
+
+/// Flushes spans not exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// W3C `traceparent` of `span`, unless it isn't exported.
+pub fn traceparent(span: &Span) -> Option<String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|p| p.inject_context(&span.context(), &mut carrier));
+    carrier.remove("traceparent")
+}
+
+/// Makes `span` continue the trace the caller sent in its headers.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent = global::get_text_map_propagator(|p| p.extract(&HeaderCarrier(headers)));
+    span.set_parent(parent);
+}
+
+struct HeaderCarrier<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderCarrier<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
@@ -13,6 +13,19 @@ impl Compiler {
         }
     }
 
+    /// Whether the template and the cargo toolchain are available.
+    pub async fn check(&self) -> Result<(), CompileError> {
+        tokio::fs::metadata(format!("{}/src/lib.mrs", self.dir)).await?;
+        let output = tokio::process::Command::new("cargo")
+            .arg("--version")
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(CompileError::Toolchain);
+        }
+        Ok(())
+    }
+
     pub async fn compile(&self, body: &str) -> Result<Vec<u8>, CompileError> {
         let path_str = format!("{}/src/lib.mrs", self.dir);
         let path = Path::new(&path_str);
@@ -60,4 +73,6 @@ pub enum CompileError {
     EncodingError(#[from] std::str::Utf8Error),
     #[error("CompileError: failed to compile code")]
     Generic,
+    #[error("CompileError: cargo toolchain unavailable")]
+    Toolchain,
 }
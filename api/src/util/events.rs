@@ -0,0 +1,251 @@
+use crate::{db::models::LogLevel, request_id::RequestId};
+use serde::Serialize;
+use std::{collections::HashMap, future::Future, sync::Mutex};
+use tokio::{
+    sync::{
+        broadcast::{self, Receiver, Sender},
+        watch,
+    },
+    time::Instant,
+};
+
+/// Events a subscriber can fall behind by before missing some.
+const CAPACITY: usize = 1024;
+
+/// Something that happened to an invocation, streamed live to
+/// subscribers of its function. Invocations are identified by the
+/// id of their record, as in the `X-Invocation-Id` header.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Started {
+        function_id: i32,
+        invocation_id: i32,
+        request_id: String,
+    },
+    Finished {
+        function_id: i32,
+        invocation_id: i32,
+        request_id: String,
+        /// "ok", "cancelled" when the caller went away, or the
+        /// `BackendError` variant the invocation failed with.
+        outcome: String,
+        error: Option<String>,
+        duration_ms: f64,
+    },
+    /// Line the function logged on a worker.
+    Log {
+        function_id: i32,
+        invocation_id: i32,
+        request_id: String,
+        dispatch_id: String,
+        level: LogLevel,
+        message: String,
+    },
+}
+
+impl Event {
+    pub fn function_id(&self) -> i32 {
+        match self {
+            Self::Started { function_id, .. }
+            | Self::Finished { function_id, .. }
+            | Self::Log { function_id, .. } => *function_id,
+        }
+    }
+
+    pub fn invocation_id(&self) -> i32 {
+        match self {
+            Self::Started { invocation_id, .. }
+            | Self::Finished { invocation_id, .. }
+            | Self::Log { invocation_id, .. } => *invocation_id,
+        }
+    }
+
+    pub fn level(&self) -> LogLevel {
+        match self {
+            Self::Finished { error: Some(_), .. } => LogLevel::Error,
+            Self::Started { .. } | Self::Finished { .. } => LogLevel::Info,
+            Self::Log { level, .. } => *level,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Started { .. } => "started",
+            Self::Finished { .. } => "finished",
+            Self::Log { .. } => "log",
+        }
+    }
+}
+
+pub struct EventBus {
+    sender: Sender<Event>,
+    /// Invocations in progress, keyed by the id their dispatch ids
+    /// derive from, so logs sent by workers can be attributed.
+    running: Mutex<HashMap<String, Running>>,
+    /// Set on shutdown, subscribers stop streaming.
+    closed: watch::Sender<bool>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let (closed, _) = watch::channel(false);
+        Self {
+            sender,
+            running: Mutex::new(HashMap::new()),
+            closed,
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Tells subscribers to stop streaming, so their connections
+    /// don't hold up a graceful shutdown.
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// Resolves once the bus is closed.
+    pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closed = self.closed.subscribe();
+        async move {
+            // Fails only when the bus is dropped, closing it as well
+            let _ = closed.wait_for(|closed| *closed).await;
+        }
+    }
+
+    fn publish(&self, event: Event) {
+        // Fails only when nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+
+    /// Publishes the start of an invocation whose dispatch ids derive
+    /// from `key`, the returned guard publishes its end.
+    pub fn started(
+        &self,
+        function_id: i32,
+        invocation_id: i32,
+        request_id: &RequestId,
+        key: &RequestId,
+    ) -> Invocation<'_> {
+        let running = Running {
+            function_id,
+            invocation_id,
+            request_id: request_id.to_string(),
+        };
+        let key = key.to_string();
+        self.running
+            .lock()
+            .unwrap()
+            .insert(key.clone(), running.clone());
+        self.publish(Event::Started {
+            function_id,
+            invocation_id,
+            request_id: running.request_id.clone(),
+        });
+        Invocation {
+            bus: self,
+            running,
+            key,
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Publishes a line logged while executing `dispatch_id`, dropped
+    /// if its invocation already finished.
+    pub fn log(&self, dispatch_id: &str, level: LogLevel, message: &str) {
+        let key = RequestId::of_dispatch(dispatch_id);
+        let running = self.running.lock().unwrap().get(key).cloned();
+        if let Some(running) = running {
+            self.publish(Event::Log {
+                function_id: running.function_id,
+                invocation_id: running.invocation_id,
+                request_id: running.request_id,
+                dispatch_id: dispatch_id.to_owned(),
+                level,
+                message: message.to_owned(),
+            });
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Running {
+    function_id: i32,
+    invocation_id: i32,
+    request_id: String,
+}
+
+/// Invocation in progress, published as cancelled if dropped
+/// before finishing.
+pub struct Invocation<'a> {
+    bus: &'a EventBus,
+    running: Running,
+    key: String,
+    started: Instant,
+    finished: bool,
+}
+
+impl Invocation<'_> {
+    pub fn finish(mut self, outcome: &str, error: Option<String>) {
+        self.publish_finished(outcome, error);
+    }
+
+    fn publish_finished(&mut self, outcome: &str, error: Option<String>) {
+        self.finished = true;
+        self.bus.running.lock().unwrap().remove(&self.key);
+        self.bus.publish(Event::Finished {
+            function_id: self.running.function_id,
+            invocation_id: self.running.invocation_id,
+            request_id: self.running.request_id.clone(),
+            outcome: outcome.to_owned(),
+            error,
+            duration_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+}
+
+impl Drop for Invocation<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.publish_finished("cancelled", None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_are_attributed_to_their_invocation() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        // Callers may reuse request ids
+        let request_id = RequestId::generate();
+        let (first, second) = (request_id.invocation_id(), request_id.invocation_id());
+        let _first = bus.started(1, 10, &request_id, &first);
+        let second_started = bus.started(1, 11, &request_id, &second);
+
+        bus.log(&second.dispatch_id(), LogLevel::Info, "hello");
+        second_started.finish("ok", None);
+        bus.log(&second.dispatch_id(), LogLevel::Info, "dropped");
+
+        let invocations: Vec<(&str, i32)> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| (event.name(), event.invocation_id()))
+            .collect();
+        assert_eq!(
+            invocations,
+            [
+                ("started", 10),
+                ("started", 11),
+                ("log", 11),
+                ("finished", 11)
+            ]
+        );
+    }
+}
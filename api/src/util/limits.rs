@@ -0,0 +1,150 @@
+use crate::{
+    db::{
+        models::{error::Error as DBError, Usage, UsageKind},
+        DBPoolConnection,
+    },
+    settings::LimitsSettings,
+};
+use std::collections::HashMap;
+use thiserror::Error;
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Idle buckets are refilled anyway, so they are dropped
+/// once the map grows past this size.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiting keyed by client identity (API key
+/// or remote address) plus daily quotas persisted in DB.
+pub struct Limiter {
+    rate: f64,
+    burst: f64,
+    daily_invocations: Option<i32>,
+    daily_deploys: Option<i32>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Limiter {
+    pub fn new(settings: &LimitsSettings) -> Self {
+        Self {
+            rate: settings.rate_per_sec,
+            burst: settings.burst as f64,
+            daily_invocations: settings.daily_invocations,
+            daily_deploys: settings.daily_deploys,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from `key`'s bucket.
+    pub async fn throttle(&self, key: &str) -> Result<(), LimitError> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            let burst = self.burst;
+            let rate = self.rate;
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.last_refill).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.rate;
+            Err(LimitError::RateLimited(Duration::from_secs_f64(wait)))
+        }
+    }
+
+    /// Records usage of `kind` for `tenant`, fails once today's quota is used up.
+    pub async fn consume_quota(
+        &self,
+        tenant: &str,
+        kind: UsageKind,
+        conn: &mut DBPoolConnection,
+    ) -> Result<(), LimitError> {
+        let quota = match kind {
+            UsageKind::Invocation => self.daily_invocations,
+            UsageKind::Deploy => self.daily_deploys,
+        };
+        let Some(quota) = quota else {
+            return Ok(());
+        };
+        let usage = Usage::record(tenant, kind, conn).await?;
+        if usage.count(kind) > quota {
+            Err(LimitError::QuotaExceeded(Usage::until_reset()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("rate limit exceeded")]
+    RateLimited(Duration),
+    #[error("daily quota exceeded")]
+    QuotaExceeded(Duration),
+    #[error("{0}")]
+    DBError(#[from] DBError),
+}
+
+impl LimitError {
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited(after) | Self::QuotaExceeded(after) => Some(*after),
+            Self::DBError(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate_per_sec: f64, burst: u32) -> Limiter {
+        Limiter::new(&LimitsSettings {
+            rate_per_sec,
+            burst,
+            daily_invocations: None,
+            daily_deploys: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn bursts_then_waits_for_refill() {
+        let limiter = limiter(20.0, 2);
+        assert!(limiter.throttle("client").await.is_ok());
+        assert!(limiter.throttle("client").await.is_ok());
+        let after = match limiter.throttle("client").await {
+            Err(e) => e.retry_after().unwrap(),
+            Ok(()) => panic!("burst exceeded without throttling"),
+        };
+        // One token at 20 per second
+        assert!(after > Duration::ZERO && after <= Duration::from_millis(50));
+
+        tokio::time::sleep(after + Duration::from_millis(10)).await;
+        assert!(limiter.throttle("client").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn clients_have_their_own_buckets() {
+        let limiter = limiter(0.1, 1);
+        assert!(limiter.throttle("first").await.is_ok());
+        assert!(limiter.throttle("first").await.is_err());
+        assert!(limiter.throttle("second").await.is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{db::DBPool, registry::WorkerInfo};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use tokio::time::Duration;
+
+/// Function label of invocations rejected before their function was
+/// resolved, the id callers sent can't be trusted as a label value.
+pub const UNRESOLVED_FUNCTION: &str = "unresolved";
+
+/// Prometheus metrics, rendered in the text exposition format
+/// at `/metrics`. Compiled modules aren't cached, so there are no
+/// cache hits to count.
+///
+/// `/metrics` is unauthenticated, meant to be scraped on the internal
+/// network, the proxies don't route it.
+pub struct Metrics {
+    registry: Registry,
+    workers_connected: IntGauge,
+    worker_in_flight: IntGaugeVec,
+    invocations: IntCounterVec,
+    invocation_duration: HistogramVec,
+    compile_duration: HistogramVec,
+    storage_duration: HistogramVec,
+    db_connections: IntGauge,
+    db_idle_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("faas".into()), None)?;
+        let instance = Self {
+            workers_connected: IntGauge::new("workers_connected", "Connected workers")?,
+            worker_in_flight: IntGaugeVec::new(
+                Opts::new("worker_in_flight", "Requests in flight per worker"),
+                &["worker"],
+            )?,
+            invocations: IntCounterVec::new(
+                Opts::new("invocations_total", "Invocations by function and outcome"),
+                &["function", "outcome"],
+            )?,
+            invocation_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "invocation_duration_seconds",
+                    "Invocation latency by function and outcome",
+                ),
+                &["function", "outcome"],
+            )?,
+            compile_duration: HistogramVec::new(
+                HistogramOpts::new("compile_duration_seconds", "Function compilation time")
+                    .buckets(vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+                &["outcome"],
+            )?,
+            storage_duration: HistogramVec::new(
+                HistogramOpts::new("storage_duration_seconds", "Storage operation latency"),
+                &["operation", "outcome"],
+            )?,
+            db_connections: IntGauge::new("db_pool_connections", "Connections held by the pool")?,
+            db_idle_connections: IntGauge::new(
+                "db_pool_idle_connections",
+                "Idle connections in the pool",
+            )?,
+            registry,
+        };
+        instance.register()?;
+        Ok(instance)
+    }
+
+    fn register(&self) -> Result<(), prometheus::Error> {
+        self.registry
+            .register(Box::new(self.workers_connected.clone()))?;
+        self.registry
+            .register(Box::new(self.worker_in_flight.clone()))?;
+        self.registry.register(Box::new(self.invocations.clone()))?;
+        self.registry
+            .register(Box::new(self.invocation_duration.clone()))?;
+        self.registry
+            .register(Box::new(self.compile_duration.clone()))?;
+        self.registry
+            .register(Box::new(self.storage_duration.clone()))?;
+        self.registry
+            .register(Box::new(self.db_connections.clone()))?;
+        self.registry
+            .register(Box::new(self.db_idle_connections.clone()))?;
+        Ok(())
+    }
+
+    /// `outcome` is "ok" or the `BackendError` variant the invocation failed with.
+    pub fn observe_invocation(&self, function: &str, outcome: &str, elapsed: Duration) {
+        self.invocations
+            .with_label_values(&[function, outcome])
+            .inc();
+        self.invocation_duration
+            .with_label_values(&[function, outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts an invocation turned away before it was dispatched,
+    /// `outcome` being the reason.
+    pub fn observe_rejection(&self, function: &str, outcome: &str) {
+        self.invocations
+            .with_label_values(&[function, outcome])
+            .inc();
+    }
+
+    pub fn observe_compile(&self, ok: bool, elapsed: Duration) {
+        self.compile_duration
+            .with_label_values(&[outcome(ok)])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_storage(&self, operation: &str, ok: bool, elapsed: Duration) {
+        self.storage_duration
+            .with_label_values(&[operation, outcome(ok)])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Samples the gauges tracking state owned elsewhere and
+    /// renders every metric.
+    pub fn render(
+        &self,
+        workers: &[WorkerInfo],
+        pool: &DBPool,
+    ) -> Result<String, prometheus::Error> {
+        self.workers_connected.set(workers.len() as i64);
+        // Disconnected workers shouldn't linger
+        self.worker_in_flight.reset();
+        for worker in workers {
+            self.worker_in_flight
+                .with_label_values(&[&worker.id.to_string()])
+                .set(worker.in_flight as i64);
+        }
+        let state = pool.state();
+        self.db_connections.set(state.connections.into());
+        self.db_idle_connections.set(state.idle_connections.into());
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+}
+
+fn outcome(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "error"
+    }
+}
@@ -1,3 +1,7 @@
 pub mod compiler;
+pub mod events;
+pub mod limits;
+pub mod metrics;
+pub mod spot_check;
 pub mod storage;
 pub mod wasm;
@@ -0,0 +1,112 @@
+use crate::{
+    db::models::Function,
+    registry::{Outcome, Reputation},
+    settings::SpotCheckSettings,
+    util::{
+        storage::Storage,
+        wasm::{self, WasmError},
+    },
+};
+use serde_json::Value as JsValue;
+use std::{io::Error as IOError, path::Path, sync::Arc};
+use thiserror::Error;
+use tokio::time::Duration;
+
+/// Re-executes a sample of the results returned by workers in a
+/// local sandbox, catching workers whose results type-check but
+/// are wrong.
+pub struct SpotChecker {
+    storage: Arc<dyn Storage + Sync + Send>,
+    sample_rate: f64,
+    prefer_local: bool,
+    fuel: u64,
+    timeout: Duration,
+}
+
+impl SpotChecker {
+    pub fn new(settings: &SpotCheckSettings, storage: Arc<dyn Storage + Sync + Send>) -> Self {
+        Self {
+            storage,
+            sample_rate: settings.sample_rate,
+            prefer_local: settings.prefer_local,
+            fuel: settings.fuel,
+            timeout: Duration::from_millis(settings.timeout_ms),
+        }
+    }
+
+    /// Compares the `remote` result of a sampled invocation with a local
+    /// execution, mismatches count against the `workers` that returned it.
+    /// Returns the result to reply with.
+    pub async fn check(
+        &self,
+        func: &Function,
+        args: &[JsValue],
+        remote: JsValue,
+        workers: &[Arc<Reputation>],
+    ) -> JsValue {
+        if self.sample_rate <= 0.0 || rand::random::<f64>() >= self.sample_rate {
+            return remote;
+        }
+        let local = match self.execute(func, args).await {
+            Ok(local) => local,
+            // Says nothing about the worker, e.g. a type the
+            // local executor doesn't support
+            Err(e) => {
+                tracing::debug!("Spot check of ({}) inconclusive: {e}", func.uri);
+                return remote;
+            }
+        };
+        let ret = func.signature.ret();
+        if ret.normalize(&local) == ret.normalize(&remote) {
+            return remote;
+        }
+        tracing::warn!(
+            "Spot check of ({}) failed, workers returned {remote}, local execution {local}",
+            func.uri
+        );
+        for worker in workers {
+            worker.record(Outcome::Disagreement);
+        }
+        if self.prefer_local {
+            local
+        } else {
+            remote
+        }
+    }
+
+    #[tracing::instrument(name = "spot_check", skip_all, fields(function = func.id))]
+    async fn execute(&self, func: &Function, args: &[JsValue]) -> Result<JsValue, SpotCheckError> {
+        // Modules are stored under their file name
+        let key = Path::new(&func.uri)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or(SpotCheckError::UnknownModule)?;
+        let bytes = self.storage.fetch(key).await?;
+        let name = func.name.clone();
+        let signature = func.signature.clone();
+        let args = args.to_vec();
+        let fuel = self.fuel;
+        let task = tokio::task::spawn_blocking(move || {
+            wasm::execute(&bytes, &name, &signature, &args, fuel)
+        });
+        let local = tokio::time::timeout(self.timeout, task)
+            .await
+            .map_err(|_| SpotCheckError::Timeout)?
+            .map_err(|_| SpotCheckError::Aborted)??;
+        Ok(local)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SpotCheckError {
+    #[error("module not found in storage")]
+    UnknownModule,
+    #[error("failed to fetch module: {0}")]
+    Fetch(#[from] IOError),
+    #[error(transparent)]
+    Execution(#[from] WasmError),
+    #[error("local execution timed out")]
+    Timeout,
+    #[error("local execution aborted")]
+    Aborted,
+}
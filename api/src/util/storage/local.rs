@@ -2,6 +2,7 @@ use super::Storage;
 use axum::async_trait;
 use std::io::Error as IOError;
 use std::path::Path;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct LocalStorage {
@@ -34,4 +35,27 @@ impl Storage for LocalStorage {
         tokio::fs::write(path, binary).await?;
         Ok(path_str)
     }
+
+    async fn check(&self) -> Result<(), IOError> {
+        // Not a module name, those end in `.wasm`, and unique so
+        // concurrent checks don't race
+        let probe = Path::new(&self.base_dir).join(format!(".readyz-{}", Uuid::new_v4()));
+        tokio::fs::write(&probe, b"ready").await?;
+        tokio::fs::remove_file(&probe).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn check_leaves_no_probe_behind() {
+        let dir = std::env::temp_dir().join(format!("faas-storage-{}", Uuid::new_v4()));
+        let storage = LocalStorage::new(dir.display().to_string());
+        storage.check().await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+        assert!(storage.check().await.is_err());
+    }
 }
@@ -0,0 +1,44 @@
+use super::Storage;
+use crate::util::metrics::Metrics;
+use axum::async_trait;
+use std::{io::Error as IOError, sync::Arc};
+use tokio::time::Instant;
+
+/// Records the latency of every operation of the wrapped storage.
+pub struct Metered<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+#[async_trait]
+impl<S: Storage + Sync + Send> Storage for Metered<S> {
+    async fn store(&self, name: &str, binary: &[u8]) -> Result<String, IOError> {
+        let started = Instant::now();
+        let result = self.inner.store(name, binary).await;
+        self.metrics
+            .observe_storage("store", result.is_ok(), started.elapsed());
+        result
+    }
+
+    async fn fetch(&self, name: &str) -> Result<Vec<u8>, IOError> {
+        let started = Instant::now();
+        let result = self.inner.fetch(name).await;
+        self.metrics
+            .observe_storage("fetch", result.is_ok(), started.elapsed());
+        result
+    }
+
+    async fn check(&self) -> Result<(), IOError> {
+        let started = Instant::now();
+        let result = self.inner.check().await;
+        self.metrics
+            .observe_storage("check", result.is_ok(), started.elapsed());
+        result
+    }
+}
@@ -1,13 +1,17 @@
 mod local;
+mod metered;
 use crate::settings::{StorageKind, StorageSettings};
 use axum::async_trait;
 pub use local::LocalStorage;
+pub use metered::Metered;
 use std::io::Error as IOError;
 
 #[async_trait]
 pub trait Storage {
     async fn store(&self, name: &str, binary: &[u8]) -> Result<String, IOError>;
     async fn fetch(&self, name: &str) -> Result<Vec<u8>, IOError>;
+    /// Whether binaries can be stored, without touching any stored one.
+    async fn check(&self) -> Result<(), IOError>;
 }
 
 pub fn init(settings: &StorageSettings) -> impl Storage {
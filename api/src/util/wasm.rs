@@ -1,7 +1,15 @@
+use crate::db::models::{FunctionType, TypeDesc};
+use serde_json::Value as JsValue;
+use std::sync::Arc;
 use thiserror::Error;
 use wasmer::{
-    imports, Function as HostFunction, FunctionEnv as HostFunctionEnv,
-    FunctionEnvMut as HostFunctionEnvMut, Instance, Module, Store,
+    imports, wasmparser::Operator, CompilerConfig, Cranelift, EngineBuilder,
+    Function as HostFunction, FunctionEnv as HostFunctionEnv, FunctionEnvMut as HostFunctionEnvMut,
+    Instance, Memory, Module, Store, Value,
+};
+use wasmer_middlewares::{
+    metering::{get_remaining_points, MeteringPoints},
+    Metering,
 };
 
 pub fn extract_description(bytes: &[u8]) -> Result<(String, Vec<u8>), WasmError> {
@@ -30,6 +38,10 @@ pub fn extract_description(bytes: &[u8]) -> Result<(String, Vec<u8>), WasmError>
             "__wbindgen_externref_table_set_null" => HostFunction::new_typed(&mut store, |_: i32|{
                 panic!("Call to __wbindgen_externref_table_set_null")
             })
+        },
+        // Imported by modules built from the boilerplate
+        "faas" => {
+            "log" => HostFunction::new_typed(&mut store, |_: i32, _: i32, _: i32| {})
         }
     };
 
@@ -62,6 +74,299 @@ pub fn extract_description(bytes: &[u8]) -> Result<(String, Vec<u8>), WasmError>
     Ok((name, result))
 }
 
+/// Runs the exported function `name` the way workers do, following
+/// the wasm-bindgen ABI. Execution is metered and aborted once `fuel`
+/// operators ran, blocks the thread until then.
+pub fn execute(
+    bytes: &[u8],
+    name: &str,
+    signature: &FunctionType,
+    args: &[JsValue],
+    fuel: u64,
+) -> Result<JsValue, WasmError> {
+    let metering = Arc::new(Metering::new(fuel, |_: &Operator| -> u64 { 1 }));
+    let mut compiler = Cranelift::default();
+    compiler.push_middleware(metering);
+    let mut store = Store::new(EngineBuilder::new(compiler));
+    let module = Module::new(&store, bytes).map_err(|_| WasmError::InvalidModule)?;
+    // Same placeholders the workers provide, describing isn't
+    // expected and externrefs aren't supported
+    let import_obj = imports! {
+        "__wbindgen_placeholder__" => {
+            "__wbindgen_describe" => HostFunction::new_typed(&mut store, |_: i32| {}),
+        },
+        "__wbindgen_externref_xform__" => {
+            "__wbindgen_externref_table_grow" => HostFunction::new_typed(&mut store, |_: i32| -> i32 { -1 }),
+            "__wbindgen_externref_table_set_null" => HostFunction::new_typed(&mut store, |_: i32| {}),
+        },
+        // Logs don't matter to spot checks
+        "faas" => {
+            "log" => HostFunction::new_typed(&mut store, |_: i32, _: i32, _: i32| {}),
+        }
+    };
+    let instance = Instance::new(&mut store, &module, &import_obj).map_err(|e| {
+        tracing::warn!("Failed to load instance: {e:?}");
+        WasmError::LoadingInstance
+    })?;
+    let func = instance
+        .exports
+        .get_function(name)
+        .map_err(|_| WasmError::FunctionNotFound)?;
+    let params = signature.params();
+    if params.len() != args.len() {
+        return Err(WasmError::BadArguments);
+    }
+
+    let mut guest = Guest {
+        store: &mut store,
+        instance: &instance,
+    };
+    let ret = signature.ret().passed_as();
+    let mut wasm_args = vec![];
+    // Values not fitting in a wasm return are written to memory
+    // at a pointer passed as first argument
+    let ret_ptr = match ret {
+        TypeDesc::String | TypeDesc::Vector(_) | TypeDesc::Slice(_) => {
+            let ptr = guest.alloc(16)?;
+            wasm_args.push(Value::I32(ptr as i32));
+            Some(ptr)
+        }
+        _ => None,
+    };
+    for (ty, arg) in params.iter().zip(args) {
+        guest.lower(ty, arg, &mut wasm_args)?;
+    }
+
+    let results = match func.call(guest.store, &wasm_args) {
+        Ok(results) => results,
+        Err(_) if guest.out_of_fuel() => return Err(WasmError::OutOfFuel),
+        Err(e) => return Err(WasmError::Trap(e.message())),
+    };
+    match ret_ptr {
+        Some(ptr) => {
+            let mut ret_bytes = [0; 8];
+            guest.read(ptr, &mut ret_bytes)?;
+            let [p0, p1, p2, p3, l0, l1, l2, l3] = ret_bytes;
+            let ptr = u32::from_le_bytes([p0, p1, p2, p3]);
+            let len = u32::from_le_bytes([l0, l1, l2, l3]);
+            guest.lift_indirect(ret, ptr, len)
+        }
+        None => lift(ret, results.first()),
+    }
+}
+
+/// Instance of a module along with its store, used to move
+/// values in and out of its memory.
+struct Guest<'a> {
+    store: &'a mut Store,
+    instance: &'a Instance,
+}
+
+impl Guest<'_> {
+    fn memory(&self) -> Result<&Memory, WasmError> {
+        self.instance
+            .exports
+            .get_memory("memory")
+            .map_err(|_| WasmError::LoadingInstance)
+    }
+
+    fn out_of_fuel(&mut self) -> bool {
+        matches!(
+            get_remaining_points(self.store, self.instance),
+            MeteringPoints::Exhausted
+        )
+    }
+
+    fn alloc(&mut self, size: u32) -> Result<u32, WasmError> {
+        let malloc = self
+            .instance
+            .exports
+            .get_function("__wbindgen_malloc")
+            .map_err(|_| WasmError::FunctionNotFound)?;
+        // Newer wasm-bindgen versions also take the alignment
+        let args = match malloc.param_arity(self.store) {
+            1 => vec![Value::I32(size as i32)],
+            _ => vec![Value::I32(size as i32), Value::I32(1)],
+        };
+        let ptr = match malloc.call(self.store, &args) {
+            Ok(results) => results.first().and_then(Value::i32),
+            Err(_) if self.out_of_fuel() => return Err(WasmError::OutOfFuel),
+            Err(e) => return Err(WasmError::Trap(e.message())),
+        };
+        ptr.map(|p| p as u32).ok_or(WasmError::CorruptFunctionDesc)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<u32, WasmError> {
+        let ptr = self.alloc(bytes.len() as u32)?;
+        let view = self.memory()?.view(self.store);
+        view.write(ptr.into(), bytes)
+            .map_err(|_| WasmError::MemoryAccess)?;
+        Ok(ptr)
+    }
+
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<(), WasmError> {
+        let view = self.memory()?.view(self.store);
+        view.read(ptr.into(), buf)
+            .map_err(|_| WasmError::MemoryAccess)
+    }
+
+    /// Converts `arg` to the wasm values `ty` is passed as.
+    fn lower(
+        &mut self,
+        ty: &TypeDesc,
+        arg: &JsValue,
+        wasm_args: &mut Vec<Value>,
+    ) -> Result<(), WasmError> {
+        match ty {
+            TypeDesc::Ref(inner) | TypeDesc::RefMut(inner) => self.lower(inner, arg, wasm_args),
+            TypeDesc::String => {
+                let s = arg.as_str().ok_or(WasmError::BadArguments)?;
+                let ptr = self.write(s.as_bytes())?;
+                wasm_args.extend([Value::I32(ptr as i32), Value::I32(s.len() as i32)]);
+                Ok(())
+            }
+            TypeDesc::Vector(inner) | TypeDesc::Slice(inner) => {
+                let items = arg.as_array().ok_or(WasmError::BadArguments)?;
+                let mut bytes = vec![];
+                for item in items {
+                    bytes.extend(to_le_bytes(inner, item)?);
+                }
+                let ptr = self.write(&bytes)?;
+                wasm_args.extend([Value::I32(ptr as i32), Value::I32(items.len() as i32)]);
+                Ok(())
+            }
+            _ => {
+                wasm_args.push(to_value(ty, arg)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads a value of type `ty` spanning `len` elements at `ptr`.
+    fn lift_indirect(&self, ty: &TypeDesc, ptr: u32, len: u32) -> Result<JsValue, WasmError> {
+        match ty {
+            TypeDesc::String => {
+                let mut bytes = vec![0; len as usize];
+                self.read(ptr, &mut bytes)?;
+                let s = String::from_utf8(bytes).map_err(|_| WasmError::MemoryAccess)?;
+                Ok(JsValue::String(s))
+            }
+            TypeDesc::Vector(inner) | TypeDesc::Slice(inner) => {
+                let size = byte_size(inner)?;
+                let mut bytes = vec![0; len as usize * size];
+                self.read(ptr, &mut bytes)?;
+                let items = bytes
+                    .chunks_exact(size)
+                    .map(|chunk| from_le_bytes(inner, chunk))
+                    .collect();
+                Ok(JsValue::Array(items))
+            }
+            _ => Err(WasmError::UnsupportedType),
+        }
+    }
+}
+
+impl TypeDesc {
+    /// Type a value is passed as, references are passed as their referent.
+    fn passed_as(&self) -> &Self {
+        match self {
+            Self::Ref(inner) | Self::RefMut(inner) => inner.passed_as(),
+            _ => self,
+        }
+    }
+}
+
+fn to_value(ty: &TypeDesc, arg: &JsValue) -> Result<Value, WasmError> {
+    let int = || arg.as_i64().ok_or(WasmError::BadArguments);
+    let value = match ty {
+        TypeDesc::I8
+        | TypeDesc::U8
+        | TypeDesc::ClampedU8
+        | TypeDesc::I16
+        | TypeDesc::U16
+        | TypeDesc::I32
+        | TypeDesc::U32 => Value::I32(int()? as i32),
+        // Either numbers or decimal strings
+        TypeDesc::I64 => Value::I64(ty.normalize(arg).as_i64().ok_or(WasmError::BadArguments)?),
+        TypeDesc::U64 => {
+            let n = ty.normalize(arg).as_u64().ok_or(WasmError::BadArguments)?;
+            Value::I64(n as i64)
+        }
+        TypeDesc::F32 => Value::F32(arg.as_f64().ok_or(WasmError::BadArguments)? as f32),
+        TypeDesc::F64 => Value::F64(arg.as_f64().ok_or(WasmError::BadArguments)?),
+        TypeDesc::Boolean => match arg {
+            JsValue::Bool(b) => Value::I32(*b as i32),
+            _ => Value::I32(int()? as i32),
+        },
+        TypeDesc::Char => match arg {
+            JsValue::String(s) => {
+                Value::I32(s.chars().next().ok_or(WasmError::BadArguments)? as i32)
+            }
+            _ => Value::I32(int()? as i32),
+        },
+        _ => return Err(WasmError::UnsupportedType),
+    };
+    Ok(value)
+}
+
+fn lift(ty: &TypeDesc, value: Option<&Value>) -> Result<JsValue, WasmError> {
+    let number = |n: f64| serde_json::Number::from_f64(n).map_or(JsValue::Null, JsValue::Number);
+    let value = match (ty, value) {
+        (TypeDesc::Unit, _) => JsValue::Null,
+        (TypeDesc::I8, Some(Value::I32(n))) => (*n as i8).into(),
+        (TypeDesc::U8 | TypeDesc::ClampedU8, Some(Value::I32(n))) => (*n as u8).into(),
+        (TypeDesc::I16, Some(Value::I32(n))) => (*n as i16).into(),
+        (TypeDesc::U16, Some(Value::I32(n))) => (*n as u16).into(),
+        (TypeDesc::I32, Some(Value::I32(n))) => (*n).into(),
+        (TypeDesc::U32, Some(Value::I32(n))) => (*n as u32).into(),
+        (TypeDesc::I64, Some(Value::I64(n))) => (*n).into(),
+        (TypeDesc::U64, Some(Value::I64(n))) => (*n as u64).into(),
+        (TypeDesc::F32, Some(Value::F32(n))) => number(*n as f64),
+        (TypeDesc::F64, Some(Value::F64(n))) => number(*n),
+        (TypeDesc::Boolean, Some(Value::I32(n))) => JsValue::Bool(*n != 0),
+        (TypeDesc::Char, Some(Value::I32(n))) => char::from_u32(*n as u32)
+            .map(String::from)
+            .ok_or(WasmError::UnsupportedType)?
+            .into(),
+        _ => return Err(WasmError::UnsupportedType),
+    };
+    Ok(value)
+}
+
+fn byte_size(ty: &TypeDesc) -> Result<usize, WasmError> {
+    match ty {
+        TypeDesc::I8 | TypeDesc::U8 | TypeDesc::ClampedU8 => Ok(1),
+        TypeDesc::I16 | TypeDesc::U16 => Ok(2),
+        TypeDesc::I32 | TypeDesc::U32 | TypeDesc::F32 => Ok(4),
+        TypeDesc::I64 | TypeDesc::U64 | TypeDesc::F64 => Ok(8),
+        _ => Err(WasmError::UnsupportedType),
+    }
+}
+
+fn to_le_bytes(ty: &TypeDesc, item: &JsValue) -> Result<Vec<u8>, WasmError> {
+    let bytes = match to_value(ty, item)? {
+        Value::I32(n) => n.to_le_bytes()[..byte_size(ty)?].to_vec(),
+        Value::I64(n) => n.to_le_bytes().to_vec(),
+        Value::F32(n) => n.to_le_bytes().to_vec(),
+        Value::F64(n) => n.to_le_bytes().to_vec(),
+        _ => return Err(WasmError::UnsupportedType),
+    };
+    Ok(bytes)
+}
+
+fn from_le_bytes(ty: &TypeDesc, chunk: &[u8]) -> JsValue {
+    let mut buf = [0; 8];
+    buf[..chunk.len()].copy_from_slice(chunk);
+    let bits = u64::from_le_bytes(buf);
+    let value = match ty {
+        TypeDesc::F32 => Value::F32(f32::from_bits(bits as u32)),
+        TypeDesc::F64 => Value::F64(f64::from_bits(bits)),
+        TypeDesc::I64 | TypeDesc::U64 => Value::I64(bits as i64),
+        _ => Value::I32(bits as i32),
+    };
+    lift(ty, Some(&value)).unwrap_or(JsValue::Null)
+}
+
 #[derive(Debug, Error)]
 pub enum WasmError {
     #[error("could not parse wasm instance")]
@@ -74,4 +379,14 @@ pub enum WasmError {
     CorruptFunctionDesc,
     #[error("multiple functions were exported, only export one function")]
     MultipleExports,
+    #[error("arguments don't match the function signature")]
+    BadArguments,
+    #[error("unsupported type")]
+    UnsupportedType,
+    #[error("out of bounds memory access")]
+    MemoryAccess,
+    #[error("execution ran out of fuel")]
+    OutOfFuel,
+    #[error("execution trapped: {0}")]
+    Trap(String),
 }
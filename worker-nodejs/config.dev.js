@@ -1,6 +1,14 @@
 let config = {
   hostUri: "api:8090",
   tlsEnabled: false,
+  // Concurrent invocations this worker accepts
+  capacity: 4,
+  // Name this worker's reputation is kept under, only with an API key
+  identity: process.env.WORKER_IDENTITY,
+  // Key with the worker scope, connects anonymously without one
+  apiKey: process.env.WORKER_API_KEY,
+  // Wire encoding, "json" or "msgpack"
+  encoding: process.env.WORKER_ENCODING || "json",
 };
 
 module.exports = config;
@@ -4,5 +4,14 @@ const config = require("./config");
 
 let kvStore = new FileSystemStore();
 console.log(config);
-let client = new WasmFaasClient(config.hostUri, config.tlsEnabled, kvStore, console.log);
+let client = new WasmFaasClient(
+  config.hostUri,
+  config.tlsEnabled,
+  kvStore,
+  console.log,
+  config.capacity,
+  config.identity,
+  config.apiKey,
+  config.encoding
+);
 client.start();
@@ -1,5 +1,6 @@
 const Executor = require("./executor");
 const axios = require("axios");
+const { encode, decode } = require("@msgpack/msgpack");
 const WebSocket = (() => {
   if (typeof window !== "undefined"){
     return BrowserWebSocketWrapper;
@@ -7,6 +8,10 @@ const WebSocket = (() => {
   return require("ws");
 })();
 
+// WS protocol version spoken by this client, the full message
+// schema is served by the API at GET /protocol/schema
+const PROTOCOL_VERSION = 1;
+
 /*
  * Expected incoming message schema
 {
@@ -20,12 +25,16 @@ const WebSocket = (() => {
     ret: { type: 'vector', content: [Object] },
     inner_ret: { type: 'vector', content: [Object] }
   },
-  args: []
+  args: [],
+  // W3C trace context, only sent when the API exports traces
+  traceparent: '00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'
 }
 */
 class BrowserWebSocketWrapper {
   constructor(url) {
     this.__ws = new WebSocket(url);
+    // MessagePack frames are decoded from array buffers
+    this.__ws.binaryType = "arraybuffer";
   }
 
   on(evt, callback) {
@@ -49,18 +58,55 @@ class BrowserWebSocketWrapper {
   }
 }
 
+/*
+ * Maps executor failures to the API's `error` message,
+ * kind is one of: trap, bad_arguments, fetch_failed, unsupported, internal
+ */
+const errorReply = (requestId, error) => {
+  let kind = "internal";
+  let trapInfo = null;
+  if (error instanceof WebAssembly.RuntimeError) {
+    kind = "trap";
+    trapInfo = {name: error.name, stack: error.stack};
+  } else if (error instanceof WebAssembly.CompileError || error instanceof WebAssembly.LinkError || error.isAxiosError) {
+    kind = "fetch_failed";
+  } else if (error.message && error.message.startsWith("Wrong number of arguments")) {
+    kind = "bad_arguments";
+  } else if (error.message && error.message.startsWith("Unsupported")) {
+    kind = "unsupported";
+  }
+  return {
+    type: "error",
+    request_id: requestId,
+    kind: kind,
+    message: String(error.message || error),
+    trap_info: trapInfo
+  };
+};
+
 class WasmFaasClient {
 
 
-  constructor(hostUri, tlsEnabled, kvStore, logger) {
+  constructor(hostUri, tlsEnabled, kvStore, logger, capacity, identity, apiKey, encoding) {
 
     if (!hostUri || !kvStore || tlsEnabled === undefined) {
       throw Error("(hostUri, tlsEnabled, kvStore) must be provided");
     }
     this.kvStore = kvStore;
+    // Ids of requests the registry cancelled
+    this.cancelled = new Set();
     this.logger = logger || (() => {});
-    // TODO: Configurable proto
-    this.wsUri = (tlsEnabled? "wss" : "ws") +"://" + hostUri + "/ws";
+    // Concurrent invocations advertised to the registry
+    this.capacity = capacity || 1;
+    this.wsUri = (tlsEnabled? "wss" : "ws") +"://" + hostUri + "/ws?capacity=" + this.capacity + "&version=" + PROTOCOL_VERSION;
+    // Reputation is kept under this name across reconnects
+    if (identity)
+      this.wsUri += "&identity=" + encodeURIComponent(identity);
+    // "msgpack" has requests sent in binary frames, byte arrays as binaries
+    this.encoding = encoding || "json";
+    this.wsUri += "&encoding=" + this.encoding;
+    // Keys with the worker scope connect as semi-trusted workers
+    this.apiKey = apiKey;
     this.httpBaseUri = (tlsEnabled? "https": "http") + "://" + hostUri + "/";
   }
 
@@ -88,7 +134,29 @@ class WasmFaasClient {
       await this.kvStore.setItem(uri, wasmModule);
     }
 
-    return await Executor.execute(wasmModule, fn, signature, args);
+    // Execution can't be interrupted, skip it if cancelled meanwhile
+    if (this.cancelled.has(msg.request_id))
+      return null;
+    let onLog = (level, message) => this.__sendLog(msg.request_id, level, message);
+    return await Executor.execute(wasmModule, fn, signature, args, onLog);
+  }
+
+  // Sends a message in the encoding negotiated when connecting
+  __send(msg) {
+    if (this.encoding === "msgpack")
+      this.__ws.send(encode(msg));
+    else
+      this.__ws.send(JSON.stringify(msg));
+  }
+
+  // Forwards a line the function logged while executing a request
+  __sendLog(requestId, level, message) {
+    this.__send({
+      type: "log",
+      request_id: requestId,
+      level: level,
+      message: message
+    });
   }
 
   async __processMessage(msg) {
@@ -96,15 +164,34 @@ class WasmFaasClient {
       case "invoke":
         {
           this.logger("[WasmFaasClient] Invoke request received, function: %s, id: %s", msg.name, msg.request_id);
-          let result = await this.__handleInvoke(msg);
-          let reply = {
-            type: "result",
-            request_id: msg.request_id,
-            content: result
-          };
-          this.logger("[WasmFaasClient] Replying to request: %s", msg.request_id);
-          return reply;
+          if (msg.traceparent)
+            this.logger("[WasmFaasClient] Continuing trace %s", msg.traceparent);
+          try {
+            let result = await this.__handleInvoke(msg);
+            if (this.cancelled.delete(msg.request_id))
+              return null;
+            let reply = {
+              type: "result",
+              request_id: msg.request_id,
+              content: result
+            };
+            this.logger("[WasmFaasClient] Replying to request: %s", msg.request_id);
+            return reply;
+          } catch (error) {
+            if (this.cancelled.delete(msg.request_id))
+              return null;
+            this.logger("[WasmFaasClient] Request %s failed: %s", msg.request_id, error.message);
+            return errorReply(msg.request_id, error);
+          }
         }
+      case "cancel":
+        this.logger("[WasmFaasClient] Request %s cancelled", msg.request_id);
+        this.cancelled.add(msg.request_id);
+        return null;
+      case "drain":
+        // Registry closes the connection once in-flight requests are done
+        this.logger("[WasmFaasClient] Draining, no new requests will be scheduled");
+        return null;
       default:
         this.ws.send("Unrecognized request type: %s", msg.type);
         throw Error("Failed to process message");
@@ -116,19 +203,22 @@ class WasmFaasClient {
     if (this.__ws)
       throw Error("Client already started");
 
-    let ws = new WebSocket(this.wsUri);
+    let ws = this.apiKey
+      ? new WebSocket(this.wsUri, { headers: { Authorization: "Bearer " + this.apiKey } })
+      : new WebSocket(this.wsUri);
     ws.on('open', async () => {
       this.logger("[WasmFaasClient] WS to %s initiated", this.wsUri);
     })
 
-    ws.on('message', async (data) => {
+    ws.on('message', async (data, isBinary) => {
       if (this.onMessageCallback)
         await this.onMessageCallback(data);
 
-      let msg = JSON.parse(data.toString());
+      let msg = isBinary ? decode(data) : JSON.parse(data.toString());
       let result = await this.__processMessage(msg);
 
-      ws.send(JSON.stringify(result));
+      if (result)
+        this.__send(result);
 
     })
 
@@ -141,6 +231,11 @@ class WasmFaasClient {
     this.__ws = ws;
   }
 
+  // Asks the registry to stop scheduling requests to this worker
+  drain() {
+    this.__send({ type: "drain" });
+  }
+
   async close() {
     await this.__ws.close();
   }
@@ -13,7 +13,7 @@ const App = () => {
 
   const handleClick = async () => {
     if (!connected) {
-      const ws = new WasmFaasClient(config.hostUri, config.tlsEnabled, kvStore, setMsg);
+      const ws = new WasmFaasClient(config.hostUri, config.tlsEnabled, kvStore, setMsg, 1, null, config.encoding);
       ws.start();
       setClient(ws);
     } else {
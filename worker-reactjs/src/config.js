@@ -1,4 +1,6 @@
 export default{
   hostUri: "skynet:8090",
   tlsEnabled: false,
+  // Wire encoding, "json" or "msgpack"
+  encoding: "json",
 }
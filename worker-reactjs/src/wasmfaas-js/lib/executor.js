@@ -37,6 +37,9 @@ const imports = {
     },
   },
 };
+// Levels of the boilerplate's faas::Level, by discriminant
+const logLevels = ["trace", "debug", "info", "warn", "error"];
+
 const maybeReturnPtr = (wasmInstance, returnSignature) => {
   let type = returnSignature.type;
   switch(type) {
@@ -152,9 +155,25 @@ const parseReturnPtr = (wasmInstance, returnSignature, returnPtr) => {
   }
 }
 
-const execute = async (wasmBytes, fn_name, signature, args) => {
-  let wasmModule = await WebAssembly.instantiate(wasmBytes, imports);
+const execute = async (wasmBytes, fn_name, signature, args, onLog) => {
+  var memory = null;
+  let wasmModule = await WebAssembly.instantiate(wasmBytes, {
+    ...imports,
+    // Imported by modules built from the boilerplate
+    faas: {
+      log: (level, ptr, len) => {
+        if (!onLog)
+          return;
+        let bytes = new Uint8Array(memory.buffer, ptr, len);
+        onLog(logLevels[level] || "info", new TextDecoder("utf-8").decode(bytes));
+      },
+    },
+  });
   let wasmInstance = wasmModule.instance;
+  memory = wasmInstance.exports.memory;
+  // Installs the panic hook
+  if (wasmInstance.exports.__faas_init)
+    wasmInstance.exports.__faas_init();
   let wasmArgs = [];
   if (signature.params.length !== args.length)
     throw Error("Wrong number of arguments, expected %d", signature.params.length);
@@ -174,6 +193,13 @@ const execute = async (wasmBytes, fn_name, signature, args) => {
   let returnedVal = fn(...wasmArgs);
 
   if (!returnPtr) {
+    // i64 and u64 come back as BigInt, JSON can't carry those past
+    // 2^53 so they're sent as decimal strings instead
+    if (typeof returnedVal === "bigint")
+      return Number.isSafeInteger(Number(returnedVal)) ? Number(returnedVal) : returnedVal.toString();
+    // Unit returns undefined, which JSON.stringify would leave out
+    if (returnedVal === undefined)
+      return null;
     return returnedVal;
   } else {
     return parseReturnPtr(wasmInstance, signature.ret, returnPtr);